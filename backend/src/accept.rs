@@ -0,0 +1,125 @@
+/// Image formats that media can be converted to, in order of preference when a client accepts
+/// several of them equally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Avif,
+    Jxl,
+    Webp,
+    Jpeg,
+}
+
+use ImageFormat::*;
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 4] = [Avif, Jxl, Webp, Jpeg];
+
+    /// Name of the format in ImageMagick
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Avif => "avif",
+            Jxl => "jxl",
+            Webp => "webp",
+            Jpeg => "jpeg",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Avif => "image/avif",
+            Jxl => "image/jxl",
+            Webp => "image/webp",
+            Jpeg => "image/jpeg",
+        }
+    }
+}
+
+// Formats that every browser displays.  Clients send wildcards like `image/*` without supporting
+// the rest, so those are only accepted when listed explicitly.
+const WIDELY_SUPPORTED: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// Media ranges of an `Accept` header with their quality
+#[derive(Debug)]
+pub struct Accept(Vec<(String, f32)>);
+
+impl Accept {
+    pub fn parse(header: &str) -> Self {
+        let ranges = header
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let mime = params.next()?.trim().to_lowercase();
+                if mime.is_empty() {
+                    return None;
+                }
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .next()
+                    .map(|q| q.trim().parse().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                Some((mime, quality))
+            })
+            .collect();
+        Self(ranges)
+    }
+
+    /// Quality of a mime type, from the most specific media range that matches it
+    pub fn quality(&self, mime: &str) -> f32 {
+        let wildcards = if WIDELY_SUPPORTED.contains(&mime) {
+            let (main_type, _) = mime.split_once('/').unwrap_or((mime, ""));
+            vec![format!("{}/*", main_type), "*/*".to_string()]
+        } else {
+            Vec::new()
+        };
+        std::iter::once(mime.to_string())
+            .chain(wildcards)
+            .find_map(|range| {
+                self.0
+                    .iter()
+                    .find(|(accepted, _)| *accepted == range)
+                    .map(|(_, quality)| *quality)
+            })
+            .unwrap_or(0.0)
+    }
+
+    pub fn accepts(&self, mime: &str) -> bool {
+        self.quality(mime) > 0.0
+    }
+
+    /// Format with the highest quality to convert images to, or WebP when none is accepted
+    pub fn best_format(&self) -> ImageFormat {
+        let mut best = (Webp, 0.0);
+        for format in ImageFormat::ALL {
+            let quality = self.quality(format.mime());
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept() {
+        let browser = Accept::parse("image/avif,image/webp,image/apng,image/*,*/*;q=0.8");
+        assert_eq!(browser.best_format(), Avif);
+        assert!(browser.accepts("image/png"));
+        assert!(!browser.accepts("image/jxl"));
+
+        let fetch = Accept::parse("*/*");
+        assert_eq!(fetch.best_format(), Webp);
+        assert!(fetch.accepts("image/jpeg"));
+        assert!(!fetch.accepts("image/avif"));
+
+        let jxl = Accept::parse("image/jxl, image/avif;q=0.9, image/jpeg;q=0.5");
+        assert_eq!(jxl.best_format(), Jxl);
+        assert!(!jxl.accepts("image/webp"));
+
+        let jpeg = Accept::parse("image/jpeg, image/webp;q=0");
+        assert_eq!(jpeg.best_format(), Jpeg);
+        assert_eq!(Accept::parse("text/html").best_format(), Webp);
+    }
+}
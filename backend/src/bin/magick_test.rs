@@ -13,7 +13,8 @@ fn main() {
     let mut file = File::open(path).unwrap();
     let mut buf = Vec::new();
     file.read_to_end(&mut buf).unwrap();
-    let buf = backend::magick::convert_to_webp(&buf, 85, 4).unwrap();
+    let buf = backend::magick::convert(&backend::magick::Source::Blob(&buf, None), "webp", 85, 4)
+        .unwrap();
     std::io::stdout().write_all(&buf).unwrap();
     // println!("{}", buf[0]);
 }
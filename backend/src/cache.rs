@@ -0,0 +1,238 @@
+use async_std::fs;
+use async_std::sync::Mutex;
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Cache on disk of files derived from media, like resized or converted images, bounded in size by evicting
+/// the least recently used files.  The files of a media are stored in a directory with the path
+/// of the media relative to the root, named after the variant and the modification time of the
+/// media, so that changed media miss the cache.
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    lru: Mutex<Lru>,
+}
+
+#[derive(Default)]
+struct Lru {
+    // Incremented on every use
+    tick: u64,
+    size: u64,
+    // Cache file -> (size, tick of the last use)
+    entries: HashMap<PathBuf, (u64, u64)>,
+    // Tick of the last use -> cache file
+    order: BTreeMap<u64, PathBuf>,
+}
+
+impl Lru {
+    fn touch(&mut self, file: &Path) -> bool {
+        let tick = self.tick;
+        match self.entries.get_mut(file) {
+            Some((_, last_use)) => {
+                self.order.remove(last_use);
+                *last_use = tick;
+                self.order.insert(tick, file.to_path_buf());
+                self.tick += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, file: PathBuf, size: u64) {
+        self.remove(&file);
+        self.entries.insert(file.clone(), (size, self.tick));
+        self.order.insert(self.tick, file);
+        self.size += size;
+        self.tick += 1;
+    }
+
+    fn remove(&mut self, file: &Path) {
+        if let Some((size, last_use)) = self.entries.remove(file) {
+            self.order.remove(&last_use);
+            self.size -= size;
+        }
+    }
+
+    // Remove the least recently used file while the cache is bigger than `max_size`
+    fn evict(&mut self, max_size: u64) -> Vec<PathBuf> {
+        let mut evicted = Vec::new();
+        while self.size > max_size {
+            let file = match self.order.values().next() {
+                Some(file) => file.clone(),
+                None => break,
+            };
+            self.remove(&file);
+            evicted.push(file);
+        }
+        evicted
+    }
+}
+
+impl DiskCache {
+    /// Open a cache directory, keeping the files already in it.  Those files are ordered by their
+    /// modification time, as their last use is unknown.
+    pub async fn new(dir: PathBuf, max_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        let mut files = Vec::new();
+        list_files(&dir, &mut files)?;
+        files.sort_by_key(|(_, _, modified)| *modified);
+        let mut lru = Lru::default();
+        for (file, size, _) in files {
+            lru.insert(file, size);
+        }
+        debug!(
+            "cache {:?}: {} files, {} bytes",
+            dir,
+            lru.entries.len(),
+            lru.size
+        );
+        let cache = Self {
+            dir,
+            max_size,
+            lru: Mutex::new(lru),
+        };
+        cache.evict().await;
+        Ok(cache)
+    }
+
+    // Cache file of a variant of a media
+    fn file(&self, path: &str, mtime: i64, variant: &str) -> PathBuf {
+        let mut file = self.dir.join(path.trim_start_matches('/'));
+        file.push(format!("{}.{}", variant, mtime));
+        file
+    }
+
+    /// Get a variant of a media with a modification time
+    pub async fn get(&self, path: &str, mtime: i64, variant: &str) -> Option<Vec<u8>> {
+        let file = self.file(path, mtime, variant);
+        if !self.lru.lock().await.touch(&file) {
+            return None;
+        }
+        match fs::read(&file).await {
+            Ok(buf) => Some(buf),
+            Err(err) => {
+                warn!("cache: cannot read {:?}: {:?}", file, err);
+                self.lru.lock().await.remove(&file);
+                None
+            }
+        }
+    }
+
+    /// Store a variant of a media with a modification time
+    pub async fn put(&self, path: &str, mtime: i64, variant: &str, buf: &[u8]) -> io::Result<()> {
+        let file = self.file(path, mtime, variant);
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        // Write to a temporary file first so that a partial file is never read
+        let mut tmp_file = file.clone().into_os_string();
+        tmp_file.push(".tmp");
+        fs::write(&tmp_file, buf).await?;
+        fs::rename(&tmp_file, &file).await?;
+        self.lru.lock().await.insert(file, buf.len() as u64);
+        self.evict().await;
+        Ok(())
+    }
+
+    /// Remove the cached variants of a media, or of all the media in a directory
+    pub async fn invalidate(&self, path: &str) {
+        let dir = self.dir.join(path.trim_start_matches('/'));
+        let mut lru = self.lru.lock().await;
+        let files: Vec<PathBuf> = lru
+            .entries
+            .keys()
+            .filter(|file| file.starts_with(&dir))
+            .cloned()
+            .collect();
+        if files.is_empty() {
+            return;
+        }
+        for file in &files {
+            lru.remove(file);
+        }
+        drop(lru);
+        if let Err(err) = fs::remove_dir_all(&dir).await {
+            warn!("cache: cannot remove {:?}: {:?}", dir, err);
+        }
+    }
+
+    async fn evict(&self) {
+        let evicted = self.lru.lock().await.evict(self.max_size);
+        for file in evicted {
+            if let Err(err) = fs::remove_file(&file).await {
+                warn!("cache: cannot remove {:?}: {:?}", file, err);
+            }
+            // Remove the directory of the media once it's empty
+            if let Some(dir) = file.parent() {
+                let _ = fs::remove_dir(dir).await;
+            }
+        }
+    }
+}
+
+// Find the cache files in a directory recursively with their size and modification time,
+// removing the temporary files left by an interrupted write.
+fn list_files(dir: &Path, files: &mut Vec<(PathBuf, u64, SystemTime)>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if metadata.is_dir() {
+            list_files(&path, files)?;
+        } else if path.extension().map(|ext| ext == "tmp").unwrap_or(false) {
+            std::fs::remove_file(&path)?;
+        } else {
+            files.push((path, metadata.len(), metadata.modified()?));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+
+    #[test]
+    fn test_disk_cache() {
+        task::block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = DiskCache::new(dir.path().to_path_buf(), 10).await.unwrap();
+            cache.put("/a/b.jpg", 1, "w100", b"0123").await.unwrap();
+            cache.put("/a/c.jpg", 1, "w100", b"4567").await.unwrap();
+            assert_eq!(
+                cache.get("/a/b.jpg", 1, "w100").await,
+                Some(b"0123".to_vec())
+            );
+            assert_eq!(cache.get("/a/b.jpg", 2, "w100").await, None);
+            assert_eq!(cache.get("/a/b.jpg", 1, "w200").await, None);
+
+            // /a/c.jpg is the least recently used
+            cache.put("/d.jpg", 1, "w100", b"89").await.unwrap();
+            cache.put("/e.jpg", 1, "w100", b"ab").await.unwrap();
+            assert_eq!(cache.get("/a/c.jpg", 1, "w100").await, None);
+            assert_eq!(
+                cache.get("/a/b.jpg", 1, "w100").await,
+                Some(b"0123".to_vec())
+            );
+            assert!(!dir.path().join("a/c.jpg").exists());
+
+            cache.put("/a/b.jpg", 2, "w100", b"cd").await.unwrap();
+            cache.invalidate("/a/b.jpg").await;
+            assert_eq!(cache.get("/a/b.jpg", 2, "w100").await, None);
+            assert!(!dir.path().join("a/b.jpg").exists());
+            cache.put("/f/g/h.jpg", 1, "webp", b"ef").await.unwrap();
+            cache.invalidate("/f").await;
+            assert_eq!(cache.get("/f/g/h.jpg", 1, "webp").await, None);
+
+            // Files are kept when the cache is opened again
+            drop(cache);
+            let cache = DiskCache::new(dir.path().to_path_buf(), 10).await.unwrap();
+            assert_eq!(cache.get("/e.jpg", 1, "w100").await, Some(b"ab".to_vec()));
+        });
+    }
+}
@@ -38,22 +38,35 @@ impl Orientation {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct Gps {
+    /// Degrees, negative for south
+    pub latitude: f64,
+    /// Degrees, negative for west
+    pub longitude: f64,
+    /// Meters, negative for below sea level
+    pub altitude: Option<f64>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Exif {
     pub date_time_original: Option<i64>,
     pub orientation: Option<Orientation>,
-    // make: Option<String>,
-    // model: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
     // software: Option<String>,
-    // // lens: Option<String>,
-    // exposure_time: Option<f64>,
-    // aperture: Option<f64>,
-    // iso_speed: Option<i64>,
-    // focal_length: Option<f64>,
-    // flash: Option<i64>,
-    // exposure_time: Option<f64>,
+    pub lens: Option<String>,
+    /// Seconds
+    pub exposure_time: Option<f64>,
+    /// F-number
+    pub aperture: Option<f64>,
+    pub iso: Option<u32>,
+    /// Millimeters
+    pub focal_length: Option<f64>,
+    /// Raw value of the exif Flash tag
+    pub flash: Option<u32>,
     // exposure_program: Option<i64>,
-    // gps: Option<(f64, f64)>
+    pub gps: Option<Gps>,
 }
 
 /*
@@ -111,6 +124,84 @@ pub fn exif_field_to_datetime(field: &exif::Field) -> Result<DateTime<Utc>, Box<
     }
 }
 
+fn field_ascii(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(vec) => {
+            let value = String::from_utf8_lossy(vec.get(0)?);
+            let value = value.trim_end_matches('\0').trim();
+            if value.is_empty() {
+                None
+            } else {
+                Some(value.to_string())
+            }
+        }
+        _ => None,
+    }
+}
+
+fn field_rationals(exif: &exif::Exif, tag: exif::Tag) -> Option<Vec<f64>> {
+    let values: Vec<f64> = match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Rational(vec) => vec.iter().map(|r| r.to_f64()).collect(),
+        exif::Value::SRational(vec) => vec.iter().map(|r| r.to_f64()).collect(),
+        _ => return None,
+    };
+    // A zero denominator gives a non-finite value
+    if values.is_empty() || values.iter().any(|v| !v.is_finite()) {
+        None
+    } else {
+        Some(values)
+    }
+}
+
+fn field_rational(exif: &exif::Exif, tag: exif::Tag) -> Option<f64> {
+    field_rationals(exif, tag).map(|values| values[0])
+}
+
+fn field_uint(exif: &exif::Exif, tag: exif::Tag) -> Option<u32> {
+    exif.get_field(tag, exif::In::PRIMARY)?.value.get_uint(0)
+}
+
+// Parse a GPS coordinate stored as (degrees, minutes, seconds) with a reference that tells the
+// hemisphere.
+fn gps_coordinate(
+    exif: &exif::Exif,
+    tag: exif::Tag,
+    ref_tag: exif::Tag,
+    negative_ref: &str,
+) -> Option<f64> {
+    let dms = field_rationals(exif, tag)?;
+    let degrees = dms[0] + dms.get(1).unwrap_or(&0.0) / 60.0 + dms.get(2).unwrap_or(&0.0) / 3600.0;
+    match field_ascii(exif, ref_tag) {
+        Some(r) if r.eq_ignore_ascii_case(negative_ref) => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+fn gps(exif: &exif::Exif) -> Option<Gps> {
+    let latitude = gps_coordinate(exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S")?;
+    let longitude = gps_coordinate(
+        exif,
+        exif::Tag::GPSLongitude,
+        exif::Tag::GPSLongitudeRef,
+        "W",
+    )?;
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return None;
+    }
+    let altitude = field_rational(exif, exif::Tag::GPSAltitude).map(|altitude| {
+        // GPSAltitudeRef is 1 when the altitude is below sea level
+        match field_uint(exif, exif::Tag::GPSAltitudeRef) {
+            Some(1) => -altitude,
+            _ => altitude,
+        }
+    });
+    Some(Gps {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
 impl Exif {
     pub fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
         let file = std::fs::File::open(path)?;
@@ -150,6 +241,15 @@ impl Exif {
         Ok(Self {
             date_time_original,
             orientation,
+            make: field_ascii(&exif, exif::Tag::Make),
+            model: field_ascii(&exif, exif::Tag::Model),
+            lens: field_ascii(&exif, exif::Tag::LensModel),
+            exposure_time: field_rational(&exif, exif::Tag::ExposureTime),
+            aperture: field_rational(&exif, exif::Tag::FNumber),
+            iso: field_uint(&exif, exif::Tag::PhotographicSensitivity),
+            focal_length: field_rational(&exif, exif::Tag::FocalLength),
+            flash: field_uint(&exif, exif::Tag::Flash),
+            gps: gps(&exif),
         })
     }
 }
@@ -178,12 +278,26 @@ mod tests {
     fn test_exif_new() {
         let exif =
             Exif::new(Path::new("/home/dev/git/exif-samples/jpg/Canon_40D.jpg")).expect("exif new");
-        assert_eq!(
-            exif,
-            Exif {
-                date_time_original: Some(1212162961),
-                orientation: None,
-            }
-        );
+        assert_eq!(exif.date_time_original, Some(1212162961));
+        assert_eq!(exif.orientation, None);
+    }
+
+    #[test]
+    fn test_exif_new_camera_gps() {
+        let exif =
+            Exif::new(Path::new("../test/samples/deer-in-the-wild_800.jpg")).expect("exif new");
+        assert_eq!(exif.date_time_original, Some(1510497678));
+        assert_eq!(exif.make.as_deref(), Some("Canon"));
+        assert_eq!(exif.model.as_deref(), Some("Canon EOS 5D Mark IV"));
+        assert_eq!(exif.lens.as_deref(), Some("EF28-300mm f/3.5-5.6L IS USM"));
+        assert_eq!(exif.exposure_time, Some(1.0 / 250.0));
+        assert_eq!(exif.aperture, Some(5.6));
+        assert_eq!(exif.iso, Some(800));
+        assert_eq!(exif.focal_length, Some(270.0));
+        assert_eq!(exif.flash, Some(16));
+        let gps = exif.gps.expect("gps");
+        assert!((gps.latitude - 50.74482).abs() < 1e-6);
+        assert!((gps.longitude - 4.283808).abs() < 1e-6);
+        assert_eq!(gps.altitude, Some(50.4));
     }
 }
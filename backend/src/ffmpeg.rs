@@ -1,13 +1,14 @@
 use std::process::Command;
 
-use crate::scanner::{ThumbError, THUMB_SIZE};
+use crate::scanner::ThumbError;
 
-pub fn make_thumb(path: &str) -> Result<Vec<u8>, ThumbError> {
+/// Make a thumbnail of a video frame fitting in a square of `size` pixels
+pub fn make_thumb(path: &str, size: u16) -> Result<Vec<u8>, ThumbError> {
     // https://ffmpeg.org/ffmpeg-all.html#thumbnail
     // ffmpeg -i in.avi -vf thumbnail,scale=300:200 -frames:v 1 out.png
     let vf_arg = format!(
         "thumbnail,scale='if(gt(iw,ih),{},trunc(oh*a/2)*2)':'if(gt(iw,ih),trunc(ow/a/2)*2,{})'",
-        THUMB_SIZE, THUMB_SIZE
+        size, size
     );
     let output = Command::new("ffmpeg")
         .arg("-v")
@@ -32,3 +33,42 @@ pub fn make_thumb(path: &str) -> Result<Vec<u8>, ThumbError> {
         ))
     }
 }
+
+#[derive(Debug, Default)]
+pub struct Probe {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    /// Seconds
+    pub duration: Option<f64>,
+}
+
+pub fn probe(path: &str) -> Result<Probe, ThumbError> {
+    // ffprobe -v error -select_streams v:0 -show_entries stream=width,height:format=duration \
+    //     -of default=noprint_wrappers=1 in.mp4
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=width,height:format=duration")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1")
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        return Err(ThumbError::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    let mut probe = Probe::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match line.split_once('=') {
+            Some(("width", v)) => probe.width = v.parse().ok(),
+            Some(("height", v)) => probe.height = v.parse().ok(),
+            Some(("duration", v)) => probe.duration = v.parse().ok(),
+            _ => {}
+        }
+    }
+    Ok(probe)
+}
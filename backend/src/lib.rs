@@ -1,8 +1,14 @@
+pub mod accept;
+pub mod cache;
 pub mod exif;
 pub mod ffmpeg;
 pub mod magick;
 pub mod models;
+pub mod range;
+pub mod raw;
 pub mod routes;
 pub mod scanner;
 pub mod state;
 pub mod utils;
+pub mod watch;
+pub mod zip;
@@ -1,20 +1,63 @@
-use magick_rust::{magick_wand_genesis, MagickError, MagickWand};
+use magick_rust::{bindings, magick_wand_genesis, MagickError, MagickWand};
 use std::sync::Once;
 
-use crate::scanner::{THUMB_QUALITY, THUMB_SIZE};
+use crate::scanner::THUMB_QUALITY;
 
 // Used to make sure MagickWand is initialized exactly once. Note that we
 // do not bother shutting down, we simply exit when we're done.
 static START: Once = Once::new();
 
-pub fn make_thumb(path: &str) -> Result<Vec<u8>, MagickError> {
+/// Image read by ImageMagick
+pub enum Source<'a> {
+    File(&'a str),
+    /// Encoded image with the EXIF orientation of the file it comes from, like the preview
+    /// embedded in a camera RAW file
+    Blob(&'a [u8], Option<u16>),
+}
+
+impl<'a> Source<'a> {
+    // Name used in logs
+    fn name(&self) -> &str {
+        match self {
+            Source::File(path) => path,
+            Source::Blob(..) => "blob",
+        }
+    }
+
+    fn read(&self, wand: &mut MagickWand) -> Result<(), MagickError> {
+        match self {
+            Source::File(path) => wand.read_image(path),
+            Source::Blob(buf, orientation) => {
+                wand.read_image_blob(buf)?;
+                match orientation {
+                    Some(orientation) => wand.set_image_orientation(*orientation as u32),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Make a thumbnail of an image fitting in each of the sizes, which must be in descending order.
+/// Every thumbnail is scaled down from the previous one to avoid decoding the image again.  The
+/// dimensions of the image once oriented are returned with the thumbnails.
+pub fn make_thumbs(
+    source: &Source,
+    sizes: &[u16],
+) -> Result<((usize, usize), Vec<(u16, Vec<u8>)>), MagickError> {
     START.call_once(|| {
         magick_wand_genesis();
     });
 
+    let path = source.name();
     let mut wand = MagickWand::new();
-    wand.read_image(path)?;
-    let (mut width, mut height) = (wand.get_image_width(), wand.get_image_height());
+    source.read(&mut wand)?;
+    wand.set_image_compression_quality(THUMB_QUALITY as usize)?;
+    if wand.get_image_orientation() != 1 {
+        wand.auto_orient();
+    }
+    let dimensions = (wand.get_image_width(), wand.get_image_height());
+    let (mut width, mut height) = dimensions;
     if width == 0 {
         log::warn!("width=0 in {}", path);
         width = 16;
@@ -23,12 +66,23 @@ pub fn make_thumb(path: &str) -> Result<Vec<u8>, MagickError> {
         log::warn!("width=0 in {}", path);
         height = 16;
     }
-    let width_ratio = THUMB_SIZE as f64 / width as f64;
-    let height_ratio = THUMB_SIZE as f64 / height as f64;
-    let (mut new_width, mut new_height) = if width_ratio < height_ratio {
-        (THUMB_SIZE as usize, (height as f64 * width_ratio) as usize)
+    let mut thumbs = Vec::with_capacity(sizes.len());
+    for size in sizes {
+        let (new_width, new_height) = thumb_dimensions(path, (width, height), *size);
+        wand.thumbnail_image(new_width, new_height);
+        thumbs.push((*size, wand.write_image_blob("webp")?));
+    }
+    Ok((dimensions, thumbs))
+}
+
+// Dimensions of an image scaled to fit in a square of `size` pixels
+fn thumb_dimensions(path: &str, (width, height): (usize, usize), size: u16) -> (usize, usize) {
+    let width_ratio = size as f64 / width as f64;
+    let height_ratio = size as f64 / height as f64;
+    let (new_width, new_height) = if width_ratio < height_ratio {
+        (size as usize, (height as f64 * width_ratio) as usize)
     } else {
-        ((width as f64 * height_ratio) as usize, THUMB_SIZE as usize)
+        ((width as f64 * height_ratio) as usize, size as usize)
     };
     if new_width == 0 || new_height == 0 {
         log::warn!(
@@ -37,28 +91,15 @@ pub fn make_thumb(path: &str) -> Result<Vec<u8>, MagickError> {
             (new_width, new_height),
             path
         );
-        new_width = 16;
-        new_height = 16;
+        return (16, 16);
     }
-    wand.set_image_compression_quality(THUMB_QUALITY as usize)?;
-
-    let orientation = wand.get_image_orientation();
-    let (new_width, new_height) = match orientation {
-        1 => (new_width, new_height),
-        o => {
-            wand.auto_orient();
-            match o {
-                6 | 8 | 5 | 7 => (new_height, new_width),
-                _ => (new_width, new_height),
-            }
-        }
-    };
-    wand.thumbnail_image(new_width, new_height);
-    wand.write_image_blob("webp")
+    (new_width, new_height)
 }
 
-pub fn convert_to_webp(
-    buf: &[u8],
+/// Encode an image in a format.  The compression speed/size only applies to webp.
+pub fn convert(
+    source: &Source,
+    format: &str,
     quality: usize,
     compression: usize,
 ) -> Result<Vec<u8>, MagickError> {
@@ -67,7 +108,7 @@ pub fn convert_to_webp(
     });
 
     let mut wand = MagickWand::new();
-    wand.read_image_blob(buf)?;
+    source.read(&mut wand)?;
     wand.set_image_compression_quality(quality)?;
     wand.set_option("webp:method", compression.to_string().as_str())?;
     wand.auto_orient();
@@ -82,5 +123,31 @@ pub fn convert_to_webp(
     //         }
     //     }
     // };
-    wand.write_image_blob("webp")
+    wand.write_image_blob(format)
+}
+
+/// Scale down an image to a width keeping its aspect ratio, and encode it in a format.  Images that
+/// are not wider than the width keep their size.
+pub fn resize(
+    source: &Source,
+    width: usize,
+    format: &str,
+    quality: usize,
+    compression: usize,
+) -> Result<Vec<u8>, MagickError> {
+    START.call_once(|| {
+        magick_wand_genesis();
+    });
+
+    let mut wand = MagickWand::new();
+    source.read(&mut wand)?;
+    wand.set_image_compression_quality(quality)?;
+    wand.set_option("webp:method", compression.to_string().as_str())?;
+    wand.auto_orient();
+    let (image_width, image_height) = (wand.get_image_width(), wand.get_image_height());
+    if width < image_width {
+        let height = (image_height * width / image_width).max(1);
+        wand.resize_image(width, height, bindings::FilterType_LanczosFilter);
+    }
+    wand.write_image_blob(format)
 }
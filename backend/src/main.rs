@@ -1,18 +1,27 @@
+use async_std::task;
 use http_types::headers::HeaderValue;
+use log::error;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 use tide::security::{CorsMiddleware, Origin};
 
+mod accept;
+mod cache;
 mod exif;
 mod ffmpeg;
 mod magick;
 mod models;
+mod range;
+mod raw;
 mod routes;
 mod scanner;
 mod state;
 mod utils;
+mod watch;
+mod zip;
 
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
@@ -54,6 +63,18 @@ struct Args {
     #[structopt(long, default_value = "0")]
     threads: usize,
 
+    /// Follow symbolic links to folders and media inside the root directory
+    #[structopt(long = "follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// Watch the root directory and index changed media as it happens
+    #[structopt(long)]
+    watch: bool,
+
+    /// Seconds between scheduled scans, which also start a scan at startup.  0 disables them
+    #[structopt(long = "scan_interval", default_value = "0")]
+    scan_interval: u64,
+
     /// Static directory which will be served at the root http path
     #[structopt(long = "static", parse(from_os_str))]
     static_dir: Option<PathBuf>,
@@ -62,11 +83,24 @@ struct Args {
     #[structopt(long = "lib_dir", parse(from_os_str))]
     lib_dir: Option<PathBuf>,
 
+    /// Comma separated sizes in pixels of the thumbnails made for each media
+    #[structopt(long = "thumb_sizes", use_delimiter = true, default_value = "512")]
+    thumb_sizes: Vec<u16>,
+
+    /// Directory where resized and converted images are cached.  Without it, they are made on every
+    /// request
+    #[structopt(long = "cache_dir", parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// Maximum size in MiB of the cache of resized and converted images
+    #[structopt(long = "cache_size", default_value = "1024")]
+    cache_size: u64,
+
     /// Number of images per page
     #[structopt(long = "page_size", default_value = "4096")]
     page_size: usize,
 
-    /// webp compression quality, from 0 to 100
+    /// Compression quality of images converted to webp, avif, jxl or jpeg, from 0 to 100
     #[structopt(long = "webp_quality", default_value = "85")]
     webp_quality: usize,
 
@@ -105,6 +139,7 @@ async fn main() -> tide::Result<()> {
     };
     assert!(args.webp_quality <= 100);
     assert!(args.webp_compression <= 6);
+    assert!(args.thumb_sizes.iter().all(|size| *size > 0));
 
     let state = state::State::new(&state::StateConfig {
         path_sqlite: &args.sqlite,
@@ -112,11 +147,28 @@ async fn main() -> tide::Result<()> {
         path_mdb: &args.mdb,
         root: &args.root,
         n_threads,
+        follow_symlinks: args.follow_symlinks,
+        thumb_sizes: args.thumb_sizes,
+        cache_dir: args.cache_dir.as_ref(),
+        cache_size: args.cache_size * 1024 * 1024,
         page_size: args.page_size,
         webp_quality: args.webp_quality,
         webp_compression: args.webp_compression,
     })
     .await?;
+    if args.watch {
+        let watch = watch::watch(state.scanner.clone(), args.root.clone());
+        task::spawn(async {
+            if let Err(err) = watch.await {
+                error!("watch: {:?}", err);
+            }
+        });
+    }
+    if args.scan_interval > 0 {
+        let scanner = state.scanner.clone();
+        let interval = Duration::from_secs(args.scan_interval);
+        task::spawn(async move { scanner.run_every(interval).await });
+    }
     let mut app = tide::with_state(state);
 
     app.with(
@@ -137,12 +189,27 @@ async fn main() -> tide::Result<()> {
     app.at("/api/folder").get(routes::get_folder);
     app.at("/api/folderRecursive")
         .get(routes::get_folder_recursive);
+    app.at("/api/search").get(routes::get_search);
+    app.at("/api/geo").get(routes::get_geo);
+    app.at("/api/timeline").get(routes::get_timeline);
+    app.at("/api/timeline/page").get(routes::get_timeline_page);
+    app.at("/api/media").get(routes::get_media);
     app.at("/api/thumb").get(routes::get_thumb);
+    app.at("/api/thumbs")
+        .get(routes::get_thumbs)
+        .post(routes::post_thumbs);
+    app.at("/api/thumb/errors").get(routes::get_thumb_errors);
+    app.at("/api/thumb/regenerate")
+        .post(routes::post_thumb_regenerate);
     app.at("/api/src/:name").get(routes::get_src);
     app.at("/api/raw/:name").get(routes::get_raw);
+    app.at("/api/download/zip")
+        .get(routes::get_download_zip)
+        .post(routes::post_download_zip);
     app.at("/api/status").get(routes::get_status);
     app.at("/api/scanner/run").post(routes::post_scan_run);
     app.at("/api/scanner/stop").post(routes::post_scan_stop);
+    app.at("/api/scanner/history").get(routes::get_scan_history);
     app.listen(args.addr).await?;
     Ok(())
 }
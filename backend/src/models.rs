@@ -1,7 +1,10 @@
 // HTTP queries
 pub mod queries {
+    use chrono::NaiveDate;
     use serde::Deserialize;
 
+    use crate::scanner::MediaType;
+
     #[derive(Debug, Deserialize)]
     pub enum Sort {
         #[serde(rename = "name")]
@@ -41,16 +44,171 @@ pub mod queries {
         pub reverse: bool,
         #[serde(default = "page_default")]
         pub page: usize,
+        /// Only media taken at or after this timestamp
+        pub from: Option<i64>,
+        /// Only media taken before this timestamp
+        pub to: Option<i64>,
+        /// Only media of these types, see `parse_media_types`
+        #[serde(rename = "type")]
+        pub media_type: Option<String>,
+    }
+
+    /// Parse a comma separated list of media types.  Besides the type names, `image` and `video`
+    /// select all the types of that kind, and a `-` prefix excludes the type instead.  If only
+    /// exclusions are given, they apply to all types.  Returns `None` on an unknown type.
+    pub fn parse_media_types(types: &str) -> Option<Vec<MediaType>> {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for name in types
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let (list, name) = match name.strip_prefix('-') {
+                Some(name) => (&mut exclude, name),
+                None => (&mut include, name),
+            };
+            match name {
+                "image" => list.extend(MediaType::ALL.iter().filter(|t| !t.is_video())),
+                "video" => list.extend(MediaType::ALL.iter().filter(|t| t.is_video())),
+                _ => list.push(name.parse().ok()?),
+            }
+        }
+        if include.is_empty() {
+            include = MediaType::ALL.to_vec();
+        }
+        Some(
+            MediaType::ALL
+                .into_iter()
+                .filter(|t| include.contains(t) && !exclude.contains(t))
+                .collect(),
+        )
+    }
+
+    fn search_dir_default() -> String {
+        "/".to_string()
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct SearchQuery {
+        pub q: String,
+        #[serde(default = "search_dir_default")]
+        pub dir: String,
+        #[serde(default = "sort_default")]
+        pub sort: Sort,
+        #[serde(default = "seed_default")]
+        pub seed: usize,
+        #[serde(default = "reverse_default")]
+        pub reverse: bool,
+        #[serde(default = "page_default")]
+        pub page: usize,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct GeoQuery {
+        #[serde(default = "search_dir_default")]
+        pub dir: String,
+        pub min_lat: f64,
+        pub max_lat: f64,
+        pub min_lon: f64,
+        pub max_lon: f64,
+        pub zoom: Option<u8>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub enum TimelineGroup {
+        #[serde(rename = "year")]
+        Year,
+        #[serde(rename = "month")]
+        Month,
+        #[serde(rename = "day")]
+        Day,
+    }
+
+    fn timeline_group_default() -> TimelineGroup {
+        TimelineGroup::Month
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct TimelineQuery {
+        #[serde(default = "search_dir_default")]
+        pub dir: String,
+        #[serde(default = "timeline_group_default")]
+        pub group: TimelineGroup,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct TimelinePageQuery {
+        pub dir: String,
+        /// Date in one of the formats returned by the timeline: `YYYY`, `YYYY-MM` or `YYYY-MM-DD`
+        pub date: String,
+    }
+
+    impl TimelinePageQuery {
+        /// Timestamp of the start of `date` in UTC
+        pub fn timestamp(&self) -> Option<i64> {
+            let date = match self.date.len() {
+                4 => format!("{}-01-01", self.date),
+                7 => format!("{}-01", self.date),
+                _ => self.date.clone(),
+            };
+            let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?;
+            Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ScanQuery {
+        /// Only scan this directory and its subdirectories
+        pub dir: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct RegenerateQuery {
+        /// Media to make a new thumbnail for
+        pub path: Option<String>,
+        /// Directory whose media, including subdirectories, get new thumbnails
+        pub dir: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct PageQuery {
+        #[serde(default = "page_default")]
+        pub page: usize,
     }
 
     #[derive(Debug, Deserialize)]
     pub struct ThumbQuery {
         pub path: String,
+        /// Requested size in pixels, served by the closest configured one
+        pub size: Option<u16>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ThumbsQuery {
+        /// Requested size in pixels, served by the closest configured one
+        pub size: Option<u16>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ZipQuery {
+        pub dir: String,
+        /// Include the media in subdirectories
+        #[serde(default)]
+        pub recursive: bool,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct MediaQuery {
+        pub dir: String,
+        pub name: String,
     }
 
     #[derive(Debug, Deserialize)]
     pub struct SrcQuery {
         pub dir: String,
+        /// Width in pixels to scale the image down to, rounded up to one of a few widths
+        pub w: Option<usize>,
     }
 }
 
@@ -58,7 +216,7 @@ pub mod queries {
 pub mod responses {
     use serde::Serialize;
 
-    use crate::models::views;
+    use crate::models::{tables, views};
     use crate::scanner;
 
     #[derive(Debug, Serialize)]
@@ -89,6 +247,58 @@ pub mod responses {
         pub total: usize,
     }
 
+    #[derive(Debug, Serialize)]
+    pub struct Search {
+        pub media: Vec<MediaDataDir>,
+        pub page: usize,
+        pub page_size: usize,
+        pub total: usize,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Media {
+        pub dir: String,
+        pub name: String,
+        pub mtime: i64,
+        pub timestamp: i64,
+        pub size: Option<i64>,
+        pub width: Option<i64>,
+        pub height: Option<i64>,
+        pub media_type: Option<scanner::MediaType>,
+        pub duration_ms: Option<i64>,
+        pub exif: Option<tables::Exif>,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Geo {
+        pub clusters: Vec<views::GeoCluster>,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct Timeline {
+        pub dates: Vec<views::DateCount>,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct TimelinePage {
+        pub page: usize,
+        pub page_size: usize,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct ThumbErrors {
+        pub errors: Vec<tables::ThumbError>,
+        pub page: usize,
+        pub page_size: usize,
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct ScanHistory {
+        pub scans: Vec<tables::Scan>,
+        pub page: usize,
+        pub page_size: usize,
+    }
+
     #[derive(Debug, Serialize)]
     pub struct ScannerReply {
         pub reply: scanner::Reply,
@@ -125,6 +335,21 @@ pub mod views {
         pub media: Option<String>,
     }
 
+    #[derive(Debug, Serialize, sqlx::FromRow)]
+    pub struct GeoCluster {
+        pub latitude: f64,
+        pub longitude: f64,
+        pub count: i64,
+        pub dir: String,
+        pub name: String,
+    }
+
+    #[derive(Debug, Serialize, sqlx::FromRow)]
+    pub struct DateCount {
+        pub date: String,
+        pub count: i64,
+    }
+
     #[derive(Debug, sqlx::FromRow)]
     pub struct FolderScan {
         pub name: String,
@@ -140,13 +365,25 @@ pub mod views {
 
 // SQL tables
 pub mod tables {
-    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    use serde::Serialize;
+
+    #[derive(Debug, Default, PartialEq, Eq, sqlx::FromRow)]
     pub struct Image {
         pub path: String,
         pub name: String,
         pub dir: String,
         pub mtime: i64,
         pub timestamp: i64,
+        #[sqlx(default)]
+        pub size: Option<i64>,
+        #[sqlx(default)]
+        pub width: Option<i64>,
+        #[sqlx(default)]
+        pub height: Option<i64>,
+        #[sqlx(default)]
+        pub duration_ms: Option<i64>,
+        #[sqlx(default)]
+        pub media_type: Option<String>,
     }
 
     #[derive(Debug, PartialEq, Eq, Hash, Clone, sqlx::FromRow)]
@@ -156,4 +393,85 @@ pub mod tables {
         pub dir: Option<String>,
         pub mtime: i64,
     }
+
+    #[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+    pub struct Exif {
+        #[serde(skip)]
+        pub path: String,
+        pub make: Option<String>,
+        pub model: Option<String>,
+        pub lens: Option<String>,
+        pub exposure_time: Option<f64>,
+        pub aperture: Option<f64>,
+        pub iso: Option<i64>,
+        pub focal_length: Option<f64>,
+        pub flash: Option<i64>,
+        pub latitude: Option<f64>,
+        pub longitude: Option<f64>,
+        pub altitude: Option<f64>,
+    }
+
+    #[derive(Debug, Serialize, sqlx::FromRow)]
+    pub struct ThumbError {
+        pub path: String,
+        pub error: String,
+        pub time: i64,
+    }
+
+    #[derive(Debug, Serialize, sqlx::FromRow)]
+    pub struct Scan {
+        pub id: i64,
+        pub start_time: i64,
+        pub end_time: Option<i64>,
+        pub added: i64,
+        pub updated: i64,
+        pub deleted: i64,
+        pub thumb_failures: i64,
+        pub state: String,
+        pub error: Option<String>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::queries::{parse_media_types, TimelinePageQuery};
+    use crate::scanner::MediaType::*;
+
+    fn timestamp(date: &str) -> Option<i64> {
+        TimelinePageQuery {
+            dir: "/".to_string(),
+            date: date.to_string(),
+        }
+        .timestamp()
+    }
+
+    #[test]
+    fn test_timeline_page_query_timestamp() {
+        assert_eq!(timestamp("2021"), Some(1609459200));
+        assert_eq!(timestamp("2021-09"), Some(1630454400));
+        assert_eq!(timestamp("2021-09-19"), Some(1632009600));
+        assert_eq!(timestamp("2021-13"), None);
+        assert_eq!(timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_media_types() {
+        assert_eq!(parse_media_types("gif"), Some(vec![Gif]));
+        assert_eq!(parse_media_types("png,jpeg"), Some(vec![Jpeg, Png]));
+        assert_eq!(
+            parse_media_types("video"),
+            Some(vec![Mp4, Mkv, Webm, Mov, Avi, M4v])
+        );
+        assert_eq!(
+            parse_media_types("image,-gif"),
+            Some(vec![Jpeg, JXL, Png, Webp, Heif, Avif, Tiff, Raw])
+        );
+        assert_eq!(
+            parse_media_types("-gif"),
+            Some(vec![
+                Jpeg, JXL, Png, Webp, Heif, Avif, Tiff, Raw, Mp4, Mkv, Webm, Mov, Avi, M4v
+            ])
+        );
+        assert_eq!(parse_media_types("gif,bmp"), None);
+    }
 }
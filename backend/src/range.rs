@@ -0,0 +1,69 @@
+/// Part of a file requested with a `Range` header
+#[derive(Debug, PartialEq, Eq)]
+pub enum Range {
+    /// No range or one that is ignored, so the whole file is served
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+impl Range {
+    /// Parse a `Range` header for a file of `len` bytes.  Only a single range of bytes is
+    /// supported, and requests with several ranges get the whole file.
+    pub fn parse(header: &str, len: u64) -> Self {
+        let spec = match header.trim().strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return Range::Full,
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some(range) => range,
+            None => return Range::Full,
+        };
+        let (start, end) = match (start.trim(), end.trim()) {
+            ("", "") => return Range::Full,
+            // Suffix with the last bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return Range::Unsatisfiable,
+                Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+                Err(_) => return Range::Full,
+            },
+            (start, "") => match start.parse() {
+                Ok(start) => (start, len.saturating_sub(1)),
+                Err(_) => return Range::Full,
+            },
+            (start, end) => match (start.parse(), end.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+                _ => return Range::Full,
+            },
+        };
+        if start >= len {
+            return Range::Unsatisfiable;
+        }
+        Range::Partial(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range() {
+        assert_eq!(Range::parse("bytes=0-99", 1000), Range::Partial(0, 99));
+        assert_eq!(Range::parse("bytes=500-", 1000), Range::Partial(500, 999));
+        assert_eq!(Range::parse("bytes=-100", 1000), Range::Partial(900, 999));
+        assert_eq!(Range::parse("bytes=-2000", 1000), Range::Partial(0, 999));
+        assert_eq!(
+            Range::parse("bytes=900-1999", 1000),
+            Range::Partial(900, 999)
+        );
+        assert_eq!(Range::parse("bytes=1000-", 1000), Range::Unsatisfiable);
+        assert_eq!(Range::parse("bytes=-0", 1000), Range::Unsatisfiable);
+        assert_eq!(Range::parse("bytes=0-", 0), Range::Unsatisfiable);
+        assert_eq!(Range::parse("bytes=0-1,5-6", 1000), Range::Full);
+        assert_eq!(Range::parse("bytes=5-1", 1000), Range::Full);
+        assert_eq!(Range::parse("items=0-1", 1000), Range::Full);
+        assert_eq!(Range::parse("bytes=a-b", 1000), Range::Full);
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// JPEG preview embedded in a camera RAW file
+#[derive(Debug)]
+pub struct Preview {
+    pub jpeg: Vec<u8>,
+    /// EXIF orientation of the RAW file, which the preview may not carry
+    pub orientation: Option<u16>,
+}
+
+// Limits against malformed files
+const MAX_IFDS: usize = 64;
+const MAX_IFD_ENTRIES: usize = 1024;
+
+// TIFF tags
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// Extract the biggest JPEG preview embedded in a camera RAW file.  Fujifilm RAF files have a
+/// header pointing to it, and the rest of the formats (CR2, NEF, ARW, DNG, ...) are based on
+/// TIFF and store previews in their IFDs.
+pub fn preview(path: &Path) -> io::Result<Option<Preview>> {
+    let mut file = File::open(path)?;
+    let mut header = [0; 16];
+    file.read_exact(&mut header)?;
+    if &header == b"FUJIFILMCCD-RAW " {
+        let mut offsets = [0; 8];
+        file.seek(SeekFrom::Start(84))?;
+        file.read_exact(&mut offsets)?;
+        let offset = u32::from_be_bytes([offsets[0], offsets[1], offsets[2], offsets[3]]);
+        let length = u32::from_be_bytes([offsets[4], offsets[5], offsets[6], offsets[7]]);
+        return Ok(read_jpeg(&mut file, offset, length)?.map(|jpeg| Preview {
+            jpeg,
+            orientation: None,
+        }));
+    }
+    let mut tiff = match Tiff::new(file, &header) {
+        Some(tiff) => tiff,
+        None => return Ok(None),
+    };
+    tiff.preview()
+}
+
+// Read a JPEG from a file if it's encoded with DCT, as the raw data of some formats is stored as
+// lossless JPEG, which is not a preview.
+fn read_jpeg(file: &mut File, offset: u32, length: u32) -> io::Result<Option<Vec<u8>>> {
+    let file_len = file.metadata()?.len();
+    if length < 4 || offset as u64 + length as u64 > file_len {
+        return Ok(None);
+    }
+    let mut jpeg = vec![0; length as usize];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut jpeg)?;
+    Ok(if is_dct_jpeg(&jpeg) { Some(jpeg) } else { None })
+}
+
+// Whether the start of frame marker of a JPEG is baseline, extended or progressive DCT
+fn is_dct_jpeg(jpeg: &[u8]) -> bool {
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return false;
+    }
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() && jpeg[pos] == 0xff {
+        match jpeg[pos + 1] {
+            0xc0..=0xc2 => return true,
+            // Other start of frame, start of scan or end of image
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf | 0xd9 | 0xda => return false,
+            _ => {}
+        }
+        pos += 2 + u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+    }
+    false
+}
+
+struct Tiff {
+    file: File,
+    big_endian: bool,
+    ifd0: u32,
+}
+
+// Entry of an IFD: tag, type, count and value or offset to the value
+struct IfdEntry(u16, u16, u32, [u8; 4]);
+
+impl Tiff {
+    fn new(file: File, header: &[u8; 16]) -> Option<Self> {
+        let big_endian = match &header[0..2] {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        let mut tiff = Self {
+            file,
+            big_endian,
+            ifd0: 0,
+        };
+        // Olympus ORF and Panasonic RW2 use their own magic numbers
+        match tiff.u16(&header[2..4]) {
+            42 | 0x4f52 | 0x5352 | 0x55 => {}
+            _ => return None,
+        }
+        tiff.ifd0 = tiff.u32(&header[4..8]);
+        Some(tiff)
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    // First value of an entry of type SHORT, LONG or IFD
+    fn value(&self, entry: &IfdEntry) -> u32 {
+        match entry.1 {
+            3 => self.u16(&entry.3) as u32,
+            _ => self.u32(&entry.3),
+        }
+    }
+
+    // Read the entries of an IFD and the offset of the next one
+    fn ifd(&mut self, offset: u32) -> io::Result<(Vec<IfdEntry>, u32)> {
+        let mut buf = [0; 12];
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.read_exact(&mut buf[..2])?;
+        let count = (self.u16(&buf) as usize).min(MAX_IFD_ENTRIES);
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            self.file.read_exact(&mut buf)?;
+            entries.push(IfdEntry(
+                self.u16(&buf[0..2]),
+                self.u16(&buf[2..4]),
+                self.u32(&buf[4..8]),
+                [buf[8], buf[9], buf[10], buf[11]],
+            ));
+        }
+        self.file.read_exact(&mut buf[..4])?;
+        Ok((entries, self.u32(&buf)))
+    }
+
+    // Offsets to the IFDs of an entry of type LONG or IFD
+    fn sub_ifds(&mut self, entry: &IfdEntry) -> io::Result<Vec<u32>> {
+        if entry.2 == 1 {
+            return Ok(vec![self.value(entry)]);
+        }
+        let count = (entry.2 as usize).min(MAX_IFDS);
+        let mut buf = vec![0; count * 4];
+        self.file.seek(SeekFrom::Start(self.u32(&entry.3) as u64))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf.chunks(4).map(|b| self.u32(b)).collect())
+    }
+
+    // Visit the IFD chain and the sub IFDs looking for the biggest JPEG
+    fn preview(&mut self) -> io::Result<Option<Preview>> {
+        let mut orientation = None;
+        // (offset, length) of the JPEGs
+        let mut candidates = Vec::new();
+        let mut pending = VecDeque::from([self.ifd0]);
+        let mut visited = HashSet::new();
+        while let Some(offset) = pending.pop_front() {
+            if offset == 0 || visited.len() >= MAX_IFDS || !visited.insert(offset) {
+                continue;
+            }
+            // Skip the IFDs out of the file
+            let (entries, next) = match self.ifd(offset) {
+                Ok(ifd) => ifd,
+                Err(_) => continue,
+            };
+            pending.push_back(next);
+            let (mut jpeg_offset, mut jpeg_length) = (None, None);
+            let (mut strip_offset, mut strip_length, mut compression) = (None, None, None);
+            for entry in &entries {
+                match entry.0 {
+                    TAG_ORIENTATION if offset == self.ifd0 => {
+                        orientation = Some(self.value(entry) as u16).filter(|o| (1..=8).contains(o))
+                    }
+                    TAG_JPEG_OFFSET => jpeg_offset = Some(self.value(entry)),
+                    TAG_JPEG_LENGTH => jpeg_length = Some(self.value(entry)),
+                    TAG_STRIP_OFFSETS if entry.2 == 1 => strip_offset = Some(self.value(entry)),
+                    TAG_STRIP_BYTE_COUNTS if entry.2 == 1 => strip_length = Some(self.value(entry)),
+                    TAG_COMPRESSION => compression = Some(self.value(entry)),
+                    TAG_SUB_IFDS => pending.extend(self.sub_ifds(entry).unwrap_or_default()),
+                    _ => {}
+                }
+            }
+            if let (Some(offset), Some(length)) = (jpeg_offset, jpeg_length) {
+                candidates.push((offset, length));
+            }
+            // Old-style and new-style JPEG compression
+            if let (Some(offset), Some(length), Some(6 | 7)) =
+                (strip_offset, strip_length, compression)
+            {
+                candidates.push((offset, length));
+            }
+        }
+
+        candidates.sort_by_key(|(_, length)| std::cmp::Reverse(*length));
+        for (offset, length) in candidates {
+            if let Some(jpeg) = read_jpeg(&mut self.file, offset, length)? {
+                return Ok(Some(Preview { jpeg, orientation }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn entry(tag: u16, typ: u16, count: u32, value: u32) -> Vec<u8> {
+        [
+            &tag.to_le_bytes()[..],
+            &typ.to_le_bytes(),
+            &count.to_le_bytes(),
+            &value.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_preview() {
+        let preview = [0xff, 0xd8, 0xff, 0xc0, 0x00, 0x02, 0xff, 0xd9];
+        let lossless = [0xff, 0xd8, 0xff, 0xc3, 0x00, 0x02, 0xff, 0xd9, 0x00, 0x00];
+        // Header, IFD0 with the orientation, the preview and a sub IFD with the lossless raw data
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(4u16.to_le_bytes());
+        tiff.extend(entry(TAG_ORIENTATION, 3, 1, 6));
+        tiff.extend(entry(TAG_JPEG_OFFSET, 4, 1, 120));
+        tiff.extend(entry(TAG_JPEG_LENGTH, 4, 1, preview.len() as u32));
+        tiff.extend(entry(TAG_SUB_IFDS, 4, 1, 64));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.resize(64, 0);
+        tiff.extend(3u16.to_le_bytes());
+        tiff.extend(entry(TAG_COMPRESSION, 3, 1, 7));
+        tiff.extend(entry(TAG_STRIP_OFFSETS, 4, 1, 130));
+        tiff.extend(entry(TAG_STRIP_BYTE_COUNTS, 4, 1, lossless.len() as u32));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.resize(120, 0);
+        tiff.extend(preview);
+        tiff.resize(130, 0);
+        tiff.extend(lossless);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&tiff).unwrap();
+        let res = super::preview(file.path()).unwrap().unwrap();
+        assert_eq!(res.jpeg, preview);
+        assert_eq!(res.orientation, Some(6));
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"not a raw file").unwrap();
+        file.write_all(&[0; 16]).unwrap();
+        assert!(super::preview(file.path()).unwrap().is_none());
+    }
+}
@@ -1,24 +1,62 @@
-use async_std::fs::File;
+use async_std::channel::{self, Sender};
+use async_std::fs::{self, File};
+use async_std::io::{BufReader, SeekFrom};
 use async_std::prelude::*;
+use async_std::task;
+use futures::TryStreamExt;
+use http_types::conditional::{ETag, IfModifiedSince, LastModified};
 use http_types::mime::Mime;
-use percent_encoding::percent_decode_str;
+use log::warn;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
-use std::path::Path;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use tide::{Body, Redirect, Response};
-use url::Url;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tide::{Body, Response};
 
+use crate::accept::{Accept, ImageFormat};
 use crate::magick;
 use crate::models::{queries, responses};
+use crate::range::Range;
 use crate::scanner::{self, MediaType};
-use crate::state::{self, Config};
+use crate::state::{self, Config, MediaFilter};
+use crate::zip::ZipWriter;
 
 const HEADER_CACHE_KEY: &str = "Cache-Control";
 const HEADER_CACHE_VALUE: &str = "max-age=3600";
+// Variant of the validators of media served without conversion
+const VARIANT_ORIGINAL: &str = "original";
+// Size of the chunks of files sent while building a ZIP archive, and how many are buffered
+const ZIP_CHUNK_LEN: usize = 64 * 1024;
+const ZIP_CHANNEL_LEN: usize = 4;
+// Name of ZIP archives that are not of a directory
+const ZIP_NAME: &str = "gallerina";
+// Bytes read from the start of a file to sniff its mime type
+const SNIFF_LEN: u64 = 4096;
+// Widths that requested widths are rounded up to, capped at the last one, so that few variants
+// of each image are converted and cached
+const SRC_WIDTHS: [usize; 7] = [320, 640, 960, 1280, 1920, 2560, 3840];
 
 pub type Request = tide::Request<state::State>;
 
+fn folder_filter(query: &queries::FolderQuery) -> tide::Result<MediaFilter> {
+    let media_types = match &query.media_type {
+        Some(types) => match queries::parse_media_types(types) {
+            Some(media_types) => Some(media_types),
+            None => return Err(http_types::Error::new(400, QueryError::InvalidMediaType)),
+        },
+        None => None,
+    };
+    Ok(MediaFilter {
+        from: query.from,
+        to: query.to,
+        media_types,
+    })
+}
+
 pub async fn get_folder(req: Request) -> tide::Result<Body> {
     let query: queries::FolderQuery = req.query()?;
     let media = req
@@ -30,6 +68,7 @@ pub async fn get_folder(req: Request) -> tide::Result<Body> {
             &query.sort,
             query.seed,
             query.reverse,
+            &folder_filter(&query)?,
         )
         .await?;
     let total = media.get(0).map(|m| m.total as usize).unwrap_or(0);
@@ -59,7 +98,13 @@ pub async fn get_folder_recursive(req: Request) -> tide::Result<Body> {
     let media = req
         .state()
         .storage
-        .folder_media_recursive(&query.dir, query.page, &query.sort, query.seed)
+        .folder_media_recursive(
+            &query.dir,
+            query.page,
+            &query.sort,
+            query.seed,
+            &folder_filter(&query)?,
+        )
         .await?;
     let total = media.get(0).map(|m| m.total as usize).unwrap_or(0);
     Body::from_json(&responses::FolderRecursive {
@@ -76,20 +121,235 @@ pub async fn get_folder_recursive(req: Request) -> tide::Result<Body> {
     })
 }
 
+pub async fn get_search(req: Request) -> tide::Result<Body> {
+    let query: queries::SearchQuery = req.query()?;
+    let media = req
+        .state()
+        .storage
+        .search(
+            &query.q,
+            &query.dir,
+            query.page,
+            &query.sort,
+            query.seed,
+            query.reverse,
+        )
+        .await?;
+    let total = media.get(0).map(|m| m.total as usize).unwrap_or(0);
+    Body::from_json(&responses::Search {
+        media: media
+            .into_iter()
+            .map(|m| responses::MediaDataDir {
+                dir: m.dir,
+                name: m.name,
+            })
+            .collect(),
+        page: query.page,
+        page_size: req.state().storage.page_size,
+        total,
+    })
+}
+
+pub async fn get_geo(req: Request) -> tide::Result<Body> {
+    let query: queries::GeoQuery = req.query()?;
+    let clusters = req
+        .state()
+        .storage
+        .geo(
+            &query.dir,
+            query.min_lat,
+            query.max_lat,
+            query.min_lon,
+            query.max_lon,
+            query.zoom,
+        )
+        .await?;
+    Body::from_json(&responses::Geo { clusters })
+}
+
+pub async fn get_timeline(req: Request) -> tide::Result<Body> {
+    let query: queries::TimelineQuery = req.query()?;
+    let dates = req
+        .state()
+        .storage
+        .timeline(&query.dir, &query.group)
+        .await?;
+    Body::from_json(&responses::Timeline { dates })
+}
+
+pub async fn get_timeline_page(req: Request) -> tide::Result<Body> {
+    let query: queries::TimelinePageQuery = req.query()?;
+    let timestamp = match query.timestamp() {
+        Some(timestamp) => timestamp,
+        None => return Err(http_types::Error::new(400, QueryError::InvalidDate)),
+    };
+    let page = req
+        .state()
+        .storage
+        .timeline_page(&query.dir, timestamp)
+        .await?;
+    Body::from_json(&responses::TimelinePage {
+        page,
+        page_size: req.state().storage.page_size,
+    })
+}
+
 pub async fn get_thumb(req: Request) -> tide::Result<Response> {
     let query: queries::ThumbQuery = req.query()?;
-    let mut body = Body::from_bytes(req.state().storage.thumb(&query.path)?);
+    let storage = &req.state().storage;
+    let size = storage.thumb_size(query.size);
+    let thumb = storage.thumb(&query.path, size)?;
+    // Thumbnails are made again without their media being modified when regenerated
+    let validators = Validators::from_content(&thumb, &size.to_string());
+    if validators.fresh(&req) {
+        return Ok(validators.not_modified());
+    }
+    let mut body = Body::from_bytes(thumb);
     body.set_mime(Mime::from_str("image/webp").expect("Mime image/webp"));
 
     let mut res = Response::new(200);
     res.set_body(body);
+    validators.apply(&mut res);
     res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
     Ok(res)
 }
 
+// Encode thumbnails one after the other, each one as the length of the path of its media and the
+// path, followed by the length of the thumbnail and the thumbnail, with lengths as little endian
+// u32.  Missing thumbnails have a length of 0.
+fn thumbs_response(paths: &[String], thumbs: Vec<Option<Vec<u8>>>) -> Response {
+    let mut buf = Vec::new();
+    for (path, thumb) in paths.iter().zip(thumbs) {
+        let thumb = thumb.unwrap_or_default();
+        buf.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        buf.extend_from_slice(path.as_bytes());
+        buf.extend_from_slice(&(thumb.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&thumb);
+    }
+    let mut body = Body::from_bytes(buf);
+    body.set_mime(Mime::from_str("application/octet-stream").unwrap());
+
+    let mut res = Response::new(200);
+    res.set_body(body);
+    res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
+    res
+}
+
+// Thumbnails of a page of a folder, in the order of `get_folder`
+pub async fn get_thumbs(req: Request) -> tide::Result<Response> {
+    let folder_query: queries::FolderQuery = req.query()?;
+    let query: queries::ThumbsQuery = req.query()?;
+    let storage = &req.state().storage;
+    let paths: Vec<String> = storage
+        .folder_media(
+            &folder_query.dir,
+            folder_query.page,
+            &folder_query.sort,
+            folder_query.seed,
+            folder_query.reverse,
+            &folder_filter(&folder_query)?,
+        )
+        .await?
+        .into_iter()
+        .map(|m| {
+            Path::new(&folder_query.dir)
+                .join(m.name)
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    let thumbs = storage.thumbs(&paths, storage.thumb_size(query.size))?;
+    Ok(thumbs_response(&paths, thumbs))
+}
+
+// Thumbnails of a JSON list of media paths, up to a page of them
+pub async fn post_thumbs(mut req: Request) -> tide::Result<Response> {
+    let query: queries::ThumbsQuery = req.query()?;
+    let paths: Vec<String> = req.body_json().await?;
+    let storage = &req.state().storage;
+    if paths.len() > storage.page_size {
+        return Err(http_types::Error::new(400, QueryError::TooManyPaths));
+    }
+    let thumbs = storage.thumbs(&paths, storage.thumb_size(query.size))?;
+    Ok(thumbs_response(&paths, thumbs))
+}
+
+pub async fn post_thumb_regenerate(req: Request) -> tide::Result<Body> {
+    let query: queries::RegenerateQuery = req.query()?;
+    let reply = match (query.path, query.dir) {
+        (Some(path), None) => {
+            if req.state().storage.media(&path).await?.is_none() {
+                return Err(http_types::Error::new(404, QueryError::MediaNotFound));
+            }
+            req.state().scanner.regenerate_thumb(&path).await?;
+            scanner::Reply::OK
+        }
+        (None, Some(dir)) => {
+            let dir = helper_query_dir(&dir)?;
+            // A scan of a missing folder would go on with its closest indexed ancestor
+            if !req
+                .state()
+                .storage
+                .folder_exists(&dir.to_string_lossy())
+                .await?
+            {
+                return Err(http_types::Error::new(404, QueryError::FolderNotFound));
+            }
+            req.state()
+                .scanner
+                .request(scanner::Request::Regenerate(dir))
+                .await
+        }
+        _ => return Err(http_types::Error::new(400, QueryError::PathOrDirRequired)),
+    };
+    Body::from_json(&responses::ScannerReply { reply })
+}
+
+pub async fn get_thumb_errors(req: Request) -> tide::Result<Body> {
+    let query: queries::PageQuery = req.query()?;
+    let errors = req.state().storage.thumb_errors(query.page).await?;
+    Body::from_json(&responses::ThumbErrors {
+        errors,
+        page: query.page,
+        page_size: req.state().storage.page_size,
+    })
+}
+
+pub async fn get_media(req: Request) -> tide::Result<Body> {
+    let query: queries::MediaQuery = req.query()?;
+    let path = Path::new(&query.dir).join(&query.name);
+    let path = path.to_string_lossy();
+    let media = match req.state().storage.media(&path).await? {
+        Some(media) => media,
+        None => return Err(http_types::Error::new(404, QueryError::MediaNotFound)),
+    };
+    let exif = req.state().storage.media_exif(&path).await?;
+    Body::from_json(&responses::Media {
+        media_type: media
+            .media_type
+            .and_then(|media_type| media_type.parse().ok()),
+        dir: media.dir,
+        name: media.name,
+        mtime: media.mtime,
+        timestamp: media.timestamp,
+        size: media.size,
+        width: media.width,
+        height: media.height,
+        duration_ms: media.duration_ms,
+        exif,
+    })
+}
+
 #[derive(Debug)]
 pub enum QueryError {
     PathOutOfRoot,
+    MediaNotFound,
+    FolderNotFound,
+    InvalidDate,
+    InvalidMediaType,
+    PathOrDirRequired,
+    InvalidWidth,
+    TooManyPaths,
 }
 
 impl fmt::Display for QueryError {
@@ -105,81 +365,452 @@ impl Error for QueryError {}
 async fn helper_get_path(req: &Request) -> tide::Result<String> {
     let name = &*percent_decode_str(req.param("name")?).decode_utf8_lossy();
     let query: queries::SrcQuery = req.query()?;
+    let path = Path::new(query.dir.strip_prefix('/').unwrap_or(&query.dir)).join(name);
+    Ok(helper_root_path(req, &path)?.to_string_lossy().to_string())
+}
+
+// Helper function to get the path in the file system of a path relative to the root, which must
+// stay in the root
+fn helper_root_path(req: &Request, path: &Path) -> tide::Result<PathBuf> {
+    // Compare against the canonical root, as symbolic links in the path are resolved below
     let root = req.state().storage.root().clone();
-    let mut path = root.join(Path::new(query.dir.strip_prefix('/').unwrap_or(&query.dir)));
-    path.push(name);
+    let root = root.canonicalize().unwrap_or(root);
+    let path = root.join(path);
     let path = path.canonicalize().unwrap_or(path);
     if !path.starts_with(root) {
         return Err(http_types::Error::new(400, QueryError::PathOutOfRoot));
     }
-    Ok(path.to_string_lossy().to_string())
+    Ok(path)
 }
 
-fn redirect_url_webp(url_str: &str) -> String {
-    let url = Url::parse(url_str).unwrap();
-    let path = url.path();
-    let query = url.query().unwrap();
-    format!("{}.re.webp?{}", path, query)
+// Path of a media relative to the root, as stored in the index
+fn helper_media_path(req: &Request, path: &str) -> String {
+    let root = req.state().storage.root();
+    let root = root.canonicalize().unwrap_or(root.clone());
+    let path = Path::new(path)
+        .strip_prefix(&root)
+        .unwrap_or(Path::new(path));
+    Path::new("/").join(path).to_string_lossy().to_string()
 }
 
-// reencode some formats into web-friendly ones
-pub async fn get_src(req: Request) -> tide::Result<Response> {
-    let path = helper_get_path(&req).await?;
+// Validators of a response for conditional requests.  For media, the entity tag is a hash of the
+// path of the media, its modification time and the variant served, as converted variants differ
+// from the original.
+struct Validators {
+    etag: ETag,
+    last_modified: Option<LastModified>,
+}
+
+impl Validators {
+    fn new(path: &str, modified: SystemTime, variant: &str) -> Self {
+        let mtime = modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut hasher = crc32fast::Hasher::new();
+        for part in [path.as_bytes(), &mtime.to_le_bytes(), variant.as_bytes()] {
+            hasher.update(&(part.len() as u32).to_le_bytes());
+            hasher.update(part);
+        }
+        Self {
+            etag: ETag::new(format!("{:x}-{:08x}", mtime, hasher.finalize())),
+            last_modified: Some(LastModified::new(UNIX_EPOCH + Duration::from_secs(mtime))),
+        }
+    }
 
-    let media_type = scanner::is_media(&Path::new(&path)).unwrap().unwrap();
-    match media_type {
-        MediaType::JXL => {
-            let new_url = redirect_url_webp(req.url().as_str());
-            return Ok(Redirect::new(new_url).into());
+    // Validators of content that can change without its media being modified, like thumbnails
+    // that are made again, from a hash of the content itself
+    fn from_content(content: &[u8], variant: &str) -> Self {
+        Self {
+            etag: ETag::new(format!("{}-{:08x}", variant, crc32fast::hash(content))),
+            last_modified: None,
         }
-        _ => {}
+    }
+
+    // Whether the client already has this response.  `If-Modified-Since` is only used when there
+    // is no `If-None-Match`.
+    fn fresh(&self, req: &Request) -> bool {
+        if let Some(if_none_match) = req.header("If-None-Match") {
+            let etag = self.etag.to_string();
+            return if_none_match.as_str().split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+            });
+        }
+        match (&self.last_modified, IfModifiedSince::from_headers(req)) {
+            (Some(last_modified), Ok(Some(since))) => last_modified.modified() <= since.modified(),
+            _ => false,
+        }
+    }
+
+    // Whether an `If-Range` header holds the current entity tag or modification time
+    fn matches(&self, if_range: &str) -> bool {
+        if_range == self.etag.to_string()
+            || self
+                .last_modified
+                .as_ref()
+                .map(|last_modified| if_range == last_modified.value().as_str())
+                .unwrap_or(false)
+    }
+
+    fn apply(&self, res: &mut Response) {
+        self.etag.apply(&mut *res);
+        if let Some(last_modified) = &self.last_modified {
+            last_modified.apply(&mut *res);
+        }
+    }
+
+    fn not_modified(&self) -> Response {
+        let mut res = Response::new(304);
+        self.apply(&mut res);
+        res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
+        res
+    }
+}
+
+// Convert a media in a blocking task, going through the cache of converted media.  Each
+// conversion of a media is stored as a different variant.
+async fn helper_convert<F>(
+    req: &Request,
+    path: String,
+    modified: SystemTime,
+    variant: &str,
+    convert: F,
+) -> tide::Result<Vec<u8>>
+where
+    F: FnOnce(&str) -> tide::Result<Vec<u8>> + Send + 'static,
+{
+    let media_path = helper_media_path(req, &path);
+    let mtime = modified.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let cache = &req.state().storage.cache;
+    if let Some(cache) = cache {
+        if let Some(buf) = cache.get(&media_path, mtime, variant).await {
+            return Ok(buf);
+        }
+    }
+
+    let buf = task::spawn_blocking(move || convert(&path)).await?;
+    if let Some(cache) = cache {
+        if let Err(err) = cache.put(&media_path, mtime, variant, &buf).await {
+            warn!("cannot cache {} {}: {:?}", media_path, variant, err);
+        }
+    }
+    Ok(buf)
+}
+
+// Serve a file streaming it from disk, or the part of it requested with a `Range` header so that
+// videos can be seeked.  The range is ignored when an `If-Range` header doesn't match the
+// validators of the file.
+async fn helper_file_response(
+    req: &Request,
+    path: &str,
+    media_type: Option<MediaType>,
+    validators: &Validators,
+) -> tide::Result<Response> {
+    let mut file = File::open(path).await?;
+    let len = file.metadata().await?.len();
+
+    let mut head = Vec::new();
+    (&mut file).take(SNIFF_LEN).read_to_end(&mut head).await?;
+    // Camera RAW files are not recognized by sniffing
+    let mime = Mime::sniff(&head).or_else(|err| match media_type {
+        Some(media_type) => Mime::from_str(media_type.mime()),
+        None => Err(err),
+    })?;
+
+    let range = match (req.header("Range"), req.header("If-Range")) {
+        (Some(_), Some(if_range)) if !validators.matches(if_range.as_str()) => Range::Full,
+        (Some(range), _) => Range::parse(range.as_str(), len),
+        (None, _) => Range::Full,
+    };
+    let mut res = match range {
+        Range::Full => Response::new(200),
+        Range::Partial(_, _) => Response::new(206),
+        Range::Unsatisfiable => {
+            let mut res = Response::new(416);
+            res.insert_header("Content-Range", format!("bytes */{}", len));
+            return Ok(res);
+        }
+    };
+    let (start, end) = match range {
+        Range::Partial(start, end) => {
+            res.insert_header("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            (start, end + 1)
+        }
+        _ => (0, len),
     };
+    file.seek(SeekFrom::Start(start)).await?;
+    let reader = BufReader::new(file.take(end - start));
+    let mut body = Body::from_reader(reader, Some((end - start) as usize));
+    body.set_mime(mime);
+
+    res.set_body(body);
+    res.insert_header("Accept-Ranges", "bytes");
+    validators.apply(&mut res);
+    res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
+    Ok(res)
+}
+
+fn converted_response(buf: Vec<u8>, format: ImageFormat, validators: &Validators) -> Response {
+    let mut body = Body::from_bytes(buf);
+    body.set_mime(Mime::from_str(format.mime()).unwrap());
+
+    let mut res = Response::new(200);
+    res.set_body(body);
+    validators.apply(&mut res);
+    res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
+    res.insert_header("Vary", "Accept");
+    res
+}
+
+// Serve media in a format accepted by the client, converting and resizing images when needed.
+// Camera RAW files are served from their embedded preview.
+pub async fn get_src(req: Request) -> tide::Result<Response> {
+    let path = helper_get_path(&req).await?;
 
+    // Older clients request conversions to webp with this suffix
     let (path, reencode_webp) = if let Some(stripped) = path.strip_suffix(".re.webp") {
         (stripped.to_string(), true)
     } else {
         (path, false)
     };
-
-    let mut file = File::open(path).await?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).await?;
-    let mime = if reencode_webp {
-        let Config {
-            webp_quality,
-            webp_compression,
-            ..
-        } = req.state().cfg;
-        buf = magick::convert_to_webp(&buf, webp_quality, webp_compression)?;
-        Mime::from_str("image/webp").unwrap()
+    let media_type = match scanner::is_media(Path::new(&path)) {
+        Ok(Some(media_type)) => media_type,
+        _ => return Err(http_types::Error::new(404, QueryError::MediaNotFound)),
+    };
+    let accept = Accept::parse(req.header("Accept").map(|v| v.as_str()).unwrap_or("*/*"));
+    let format = if reencode_webp {
+        ImageFormat::Webp
     } else {
-        Mime::sniff(&buf)?
+        accept.best_format()
     };
 
-    let mut body = Body::from_bytes(buf);
-    body.set_mime(mime);
+    let Config {
+        webp_quality,
+        webp_compression,
+        ..
+    } = req.state().cfg;
+    // Animations and videos are not resized
+    let query: queries::SrcQuery = req.query()?;
+    let width = match (media_type, query.w) {
+        (MediaType::Gif, _) => None,
+        (media_type, _) if media_type.is_video() => None,
+        (_, Some(0)) => return Err(http_types::Error::new(400, QueryError::InvalidWidth)),
+        (_, Some(width)) => {
+            let width = SRC_WIDTHS
+                .iter()
+                .copied()
+                .find(|w| *w >= width)
+                .unwrap_or(SRC_WIDTHS[SRC_WIDTHS.len() - 1]);
+            // Images that are not wider are served at their own width
+            let media = req
+                .state()
+                .storage
+                .media(&helper_media_path(&req, &path))
+                .await?;
+            match media.and_then(|media| media.width) {
+                Some(media_width) if media_width <= width as i64 => None,
+                _ => Some(width),
+            }
+        }
+        (_, None) => None,
+    };
+    let convert = reencode_webp || !(media_type.is_video() || accept.accepts(media_type.mime()));
+    let variant = match width {
+        Some(width) => format!("w{}.{}", width, format.as_str()),
+        None if convert => format.as_str().to_string(),
+        None => VARIANT_ORIGINAL.to_string(),
+    };
+    let modified = fs::metadata(&path).await?.modified()?;
+    let validators = Validators::new(&helper_media_path(&req, &path), modified, &variant);
+    if validators.fresh(&req) {
+        let mut res = validators.not_modified();
+        res.insert_header("Vary", "Accept");
+        return Ok(res);
+    }
 
-    let mut res = Response::new(200);
-    res.set_body(body);
-    res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
+    if let Some(width) = width {
+        let buf = helper_convert(&req, path, modified, &variant, move |path| {
+            let preview = scanner::raw_preview(Path::new(path))?;
+            Ok(magick::resize(
+                &scanner::magick_source(path, &preview),
+                width,
+                format.as_str(),
+                webp_quality,
+                webp_compression,
+            )?)
+        })
+        .await?;
+        return Ok(converted_response(buf, format, &validators));
+    }
+    if convert {
+        let buf = helper_convert(&req, path, modified, &variant, move |path| {
+            let preview = scanner::raw_preview(Path::new(path))?;
+            Ok(magick::convert(
+                &scanner::magick_source(path, &preview),
+                format.as_str(),
+                webp_quality,
+                webp_compression,
+            )?)
+        })
+        .await?;
+        return Ok(converted_response(buf, format, &validators));
+    }
+
+    let mut res = helper_file_response(&req, &path, Some(media_type), &validators).await?;
+    res.insert_header("Vary", "Accept");
     Ok(res)
 }
 
 // get the original file without reencoding
 pub async fn get_raw(req: Request) -> tide::Result<Response> {
     let path = helper_get_path(&req).await?;
-    let mut file = File::open(path).await?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).await?;
+    let media_type = scanner::is_media(Path::new(&path)).unwrap_or(None);
+    let modified = fs::metadata(&path).await?.modified()?;
+    let validators = Validators::new(&helper_media_path(&req, &path), modified, VARIANT_ORIGINAL);
+    if validators.fresh(&req) {
+        return Ok(validators.not_modified());
+    }
+    helper_file_response(&req, &path, media_type, &validators).await
+}
 
-    let mime = Mime::sniff(&buf)?;
-    let mut body = Body::from_bytes(buf);
-    body.set_mime(mime);
+// File added to a ZIP archive
+struct ZipFile {
+    name: String,
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+}
+
+// Helper function to get the files to add to a ZIP archive from the paths of media, skipping the
+// ones that are missing and the ones already added.  Fails with 404 when none is left.
+async fn helper_zip_files(
+    req: &Request,
+    media_paths: &[String],
+    base: &str,
+) -> tide::Result<Vec<ZipFile>> {
+    let mut files = Vec::with_capacity(media_paths.len());
+    let mut added = HashSet::new();
+    for path in media_paths {
+        match helper_zip_file(req, path, base).await {
+            Ok(file) if added.insert(file.path.clone()) => files.push(file),
+            Ok(_) => {}
+            // The index may not be up to date
+            Err(err) if err.status() == 404 => warn!("zip: {} not found", path),
+            Err(err) => return Err(err),
+        }
+    }
+    if files.is_empty() {
+        return Err(http_types::Error::new(404, QueryError::MediaNotFound));
+    }
+    Ok(files)
+}
+
+// Helper function to get a file to add to a ZIP archive from the path of a media, stored in the
+// archive with its canonical path relative to `base`, or else to the root
+async fn helper_zip_file(req: &Request, media_path: &str, base: &str) -> tide::Result<ZipFile> {
+    let path = helper_root_path(req, Path::new(media_path.trim_start_matches('/')))?;
+    let metadata = match fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Err(http_types::Error::new(404, QueryError::MediaNotFound)),
+    };
+    let canonical = helper_media_path(req, &path.to_string_lossy());
+    let canonical = Path::new(&canonical);
+    let name = canonical
+        .strip_prefix(base)
+        .or_else(|_| canonical.strip_prefix("/"))
+        .unwrap_or(canonical);
+    Ok(ZipFile {
+        name: name.to_string_lossy().to_string(),
+        path,
+        len: metadata.len(),
+        modified: metadata.modified()?,
+    })
+}
+
+// Write a ZIP archive of files to a channel as it's built, stopping when the receiver is dropped
+// because the client went away.  Files removed since they were listed are skipped.
+async fn zip_stream(files: Vec<ZipFile>, sender: Sender<io::Result<Vec<u8>>>) {
+    let mut zip = ZipWriter::new();
+    for file in files {
+        let reader = match File::open(&file.path).await {
+            Ok(reader) => reader,
+            Err(err) => {
+                warn!("zip: cannot open {:?}: {:?}", file.path, err);
+                continue;
+            }
+        };
+        let header = zip.start_file(&file.name, file.len, file.modified);
+        if sender.send(Ok(header)).await.is_err() {
+            return;
+        }
+        let mut reader = reader.take(file.len);
+        loop {
+            let mut buf = vec![0; ZIP_CHUNK_LEN];
+            let n = match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) => {
+                    warn!("zip: cannot read {:?}: {:?}", file.path, err);
+                    let _ = sender.send(Err(err)).await;
+                    return;
+                }
+            };
+            buf.truncate(n);
+            zip.data(&buf);
+            if sender.send(Ok(buf)).await.is_err() {
+                return;
+            }
+        }
+        if sender.send(Ok(zip.end_file())).await.is_err() {
+            return;
+        }
+    }
+    let _ = sender.send(Ok(zip.finish())).await;
+}
+
+fn zip_response(files: Vec<ZipFile>, name: &str) -> Response {
+    let (sender, receiver) = channel::bounded(ZIP_CHANNEL_LEN);
+    task::spawn(zip_stream(files, sender));
+    let mut body = Body::from_reader(receiver.into_async_read(), None);
+    body.set_mime(Mime::from_str("application/zip").unwrap());
 
     let mut res = Response::new(200);
     res.set_body(body);
-    res.insert_header(HEADER_CACHE_KEY, HEADER_CACHE_VALUE);
-    Ok(res)
+    res.insert_header(
+        "Content-Disposition",
+        format!(
+            "attachment; filename*=UTF-8''{}.zip",
+            utf8_percent_encode(name, NON_ALPHANUMERIC)
+        ),
+    );
+    res
+}
+
+// Download the originals of the media in a directory as a ZIP archive, with the paths relative
+// to the directory
+pub async fn get_download_zip(req: Request) -> tide::Result<Response> {
+    let query: queries::ZipQuery = req.query()?;
+    let dir = helper_query_dir(&query.dir)?;
+    let dir = dir.to_string_lossy();
+    let paths = req
+        .state()
+        .storage
+        .folder_media_paths(&dir, query.recursive)
+        .await?;
+    let files = helper_zip_files(&req, &paths, &dir).await?;
+    let name = Path::new(&*dir)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| ZIP_NAME.to_string());
+    Ok(zip_response(files, &name))
+}
+
+// Download the originals of a JSON list of media paths as a ZIP archive
+pub async fn post_download_zip(mut req: Request) -> tide::Result<Response> {
+    let paths: Vec<String> = req.body_json().await?;
+    let files = helper_zip_files(&req, &paths, "/").await?;
+    Ok(zip_response(files, ZIP_NAME))
 }
 
 pub async fn get_status(req: Request) -> tide::Result<Body> {
@@ -190,11 +821,40 @@ pub async fn get_status(req: Request) -> tide::Result<Body> {
     })
 }
 
+// Helper function to get a directory relative to the root from a query, used by the scanner
+// requests
+fn helper_query_dir(dir: &str) -> tide::Result<PathBuf> {
+    // Collected from its components to drop trailing slashes, as folders are stored without them
+    let dir: PathBuf = Path::new("/")
+        .join(dir.trim_start_matches('/'))
+        .components()
+        .collect();
+    if dir.components().any(|c| c == Component::ParentDir) {
+        return Err(http_types::Error::new(400, QueryError::PathOutOfRoot));
+    }
+    Ok(dir)
+}
+
 pub async fn post_scan_run(req: Request) -> tide::Result<Body> {
-    let reply = req.state().scanner.request(scanner::Request::Run).await;
+    let query: queries::ScanQuery = req.query()?;
+    let request = match query.dir {
+        Some(dir) => scanner::Request::RunPath(helper_query_dir(&dir)?),
+        None => scanner::Request::Run,
+    };
+    let reply = req.state().scanner.request(request).await;
     Body::from_json(&responses::ScannerReply { reply })
 }
 
+pub async fn get_scan_history(req: Request) -> tide::Result<Body> {
+    let query: queries::PageQuery = req.query()?;
+    let scans = req.state().storage.scan_history(query.page).await?;
+    Body::from_json(&responses::ScanHistory {
+        scans,
+        page: query.page,
+        page_size: req.state().storage.page_size,
+    })
+}
+
 pub async fn post_scan_stop(req: Request) -> tide::Result<Body> {
     let reply = req.state().scanner.request(scanner::Request::Stop).await;
     Body::from_json(&responses::ScannerReply { reply })
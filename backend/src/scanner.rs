@@ -5,18 +5,20 @@ use async_std::task::{self, JoinHandle};
 use awaitgroup::{WaitGroup, Worker};
 use chrono::prelude::*;
 use futures::executor::ThreadPoolBuilder;
-use log::{debug, error};
+use log::{debug, error, warn};
 use parallel_stream::{from_stream, prelude::*};
 use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::mem;
+use std::os::unix::fs::MetadataExt;
 // use std::fs;
 use async_std::sync::{Arc, Mutex, RwLock};
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time;
 use std::time::Duration;
 
@@ -25,6 +27,7 @@ use crate::ffmpeg;
 use crate::magick;
 use crate::models::tables;
 use crate::models::views;
+use crate::raw;
 use crate::state::Storage;
 // use crate::utils::MediaType::*;
 
@@ -32,6 +35,8 @@ pub const THUMB_SIZE: u16 = 512;
 pub const THUMB_QUALITY: u8 = 70; // WEBP
 pub const MAX_SQL_TX_SIZE: usize = 1024;
 pub const THUMBS_CHUNK_SIZE: usize = 32;
+// Delay before trying again a scheduled scan while the scanner is busy
+const SCAN_RETRY_INTERVAL: Duration = Duration::from_secs(10);
 
 #[allow(unused)]
 #[derive(Debug)]
@@ -39,6 +44,7 @@ pub enum ScanError {
     Io(PathBuf, io::Error),
     Sqlx(sqlx::Error),
     Heed(heed::Error),
+    Thumb(ThumbError),
 }
 
 impl From<heed::Error> for ScanError {
@@ -53,6 +59,12 @@ impl From<sqlx::Error> for ScanError {
     }
 }
 
+impl From<ThumbError> for ScanError {
+    fn from(error: ThumbError) -> Self {
+        Self::Thumb(error)
+    }
+}
+
 impl fmt::Display for ScanError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -101,6 +113,10 @@ pub struct Stats {
     scan_files_count: usize,
     scan_folders_total: usize,
     scan_files_total: usize,
+    scan_files_added: usize,
+    scan_files_updated: usize,
+    scan_files_deleted: usize,
+    scan_thumb_failures: usize,
 }
 
 impl Stats {
@@ -112,6 +128,10 @@ impl Stats {
             scan_files_count: 0,
             scan_folders_total: 0,
             scan_files_total: 0,
+            scan_files_added: 0,
+            scan_files_updated: 0,
+            scan_files_deleted: 0,
+            scan_thumb_failures: 0,
         }
     }
 
@@ -120,6 +140,10 @@ impl Stats {
         self.scan_files_count = 0;
         self.scan_folders_total = 0;
         self.scan_files_total = 0;
+        self.scan_files_added = 0;
+        self.scan_files_updated = 0;
+        self.scan_files_deleted = 0;
+        self.scan_thumb_failures = 0;
     }
 }
 
@@ -128,15 +152,34 @@ pub struct Scanner {
     state: Arc<RwLock<ScanState>>,
     storage: Storage,
     n_threads: usize,
+    follow_symlinks: bool,
     stats: Arc<RwLock<Stats>>,
+    // Id of the current scan in the history
+    scan_id: Arc<Mutex<Option<i64>>>,
 }
 
 #[derive(Debug)]
 pub enum Request {
     Run,
+    /// Refresh the index of the given directories, relative to the root, without walking the whole
+    /// tree.
+    Refresh(Vec<PathBuf>),
+    /// Scan and index the subtree of a directory, relative to the root
+    RunPath(PathBuf),
+    /// Like `RunPath`, but index all the media again, making new thumbnails
+    Regenerate(PathBuf),
     Stop,
 }
 
+// Part of the root directory to scan
+#[derive(Debug)]
+enum ScanTarget {
+    Root,
+    Refresh(Vec<PathBuf>),
+    // A subtree, and whether to index all its media again
+    Path(PathBuf, bool),
+}
+
 #[derive(Debug, Serialize)]
 pub enum Reply {
     Idle,
@@ -145,34 +188,72 @@ pub enum Reply {
 }
 
 impl Scanner {
-    pub fn new(storage: Storage, n_threads: usize) -> Self {
+    pub fn new(storage: Storage, n_threads: usize, follow_symlinks: bool) -> Self {
         Self {
             state: Arc::new(RwLock::new(ScanState::Idle)),
             storage,
             n_threads,
+            follow_symlinks,
             stats: Arc::new(RwLock::new(Stats::new())),
+            scan_id: Arc::new(Mutex::new(None)),
         }
     }
 
-    async fn task_fn_scan_dir(self) {
-        let scan_dir = match scan_dir(self.stats.clone(), self.storage.root.clone()).await {
-            Ok(scan_dir) => scan_dir,
+    async fn task_fn_scan_dir(self, target: ScanTarget) {
+        let force = matches!(target, ScanTarget::Path(_, true));
+        let scans = match target {
+            ScanTarget::Root => scan_dir(
+                self.stats.clone(),
+                self.storage.root.clone(),
+                self.follow_symlinks,
+            )
+            .await
+            .map(|scan_dir| vec![(PathBuf::from("/"), scan_dir)]),
+            ScanTarget::Refresh(dirs) => {
+                scan_dirs(
+                    &self.storage,
+                    self.stats.clone(),
+                    dirs,
+                    self.follow_symlinks,
+                    true,
+                )
+                .await
+            }
+            ScanTarget::Path(dir, _) => {
+                scan_dirs(
+                    &self.storage,
+                    self.stats.clone(),
+                    vec![dir],
+                    self.follow_symlinks,
+                    false,
+                )
+                .await
+            }
+        };
+        let scans = match scans {
+            Ok(scans) => scans,
             Err(err) => {
                 error!("scan_dir: {:?}", err);
+                self.history_end("error", Some(format!("scan_dir: {:?}", err)))
+                    .await;
                 // State transition to Error
                 debug!("-> ScanState::Error");
                 *self.state.write().await = ScanState::Error(format!("scan_dir: {:?}", err));
                 return;
             }
         };
-        let (indexer, indexer_handle) =
-            Indexer::start(self.storage.clone(), self.stats.clone(), self.n_threads);
+        let (indexer, indexer_handle) = Indexer::start(
+            self.storage.clone(),
+            self.stats.clone(),
+            self.n_threads,
+            force,
+        );
         // State transition to Indexing
         debug!("-> ScanState::Indexing");
         *self.state.write().await = ScanState::Indexing(
             task::spawn(
                 self.clone()
-                    .task_fn_index(indexer, indexer_handle.clone(), scan_dir),
+                    .task_fn_index(indexer, indexer_handle.clone(), scans),
             ),
             indexer_handle,
         );
@@ -182,42 +263,54 @@ impl Scanner {
         self,
         indexer: Indexer,
         mut indexer_handle: IndexerHandle,
-        scan_dir: ScanDir,
+        scans: Vec<(PathBuf, ScanDir)>,
     ) {
-        match indexer.update(&scan_dir).await {
-            Ok(_) => {}
-            Err(err) => {
-                error!("indexer update: {:?}", err);
-                // State transition to Error
-                debug!("-> ScanState::Error");
-                *self.state.write().await = ScanState::Error(format!("indexer update: {:?}", err));
-                return;
+        for (parent, scan_dir) in &scans {
+            match indexer.update(parent, scan_dir).await {
+                Ok(_) => {}
+                Err(err) => {
+                    error!("indexer update: {:?}", err);
+                    self.history_end("error", Some(format!("indexer update: {:?}", err)))
+                        .await;
+                    // State transition to Error
+                    debug!("-> ScanState::Error");
+                    *self.state.write().await =
+                        ScanState::Error(format!("indexer update: {:?}", err));
+                    return;
+                }
             }
         }
         indexer_handle.wait_stop().await;
         self.stats.write().await.last_scan_end = Some(Local::now());
+        self.history_end("done", None).await;
         // State transition to Idle
         debug!("-> ScanState::Idle");
         *self.state.write().await = ScanState::Idle;
     }
 
+    async fn run(&self, target: ScanTarget) -> Reply {
+        let mut state = self.state.write().await;
+        if let ScanState::Idle | ScanState::Error(_) = &*state {
+            let mut stats = self.stats.write().await;
+            stats.last_scan_start = Some(Local::now());
+            stats.reset_counters();
+            drop(stats);
+            self.history_start().await;
+            // State transition to Scanning
+            debug!("-> ScanState::Scanning");
+            *state = ScanState::Scanning(task::spawn(self.clone().task_fn_scan_dir(target)));
+            Reply::OK
+        } else {
+            Reply::NotIdle
+        }
+    }
+
     pub async fn request(&self, r: Request) -> Reply {
         match r {
-            Request::Run => {
-                let mut state = self.state.write().await;
-                if let ScanState::Idle | ScanState::Error(_) = &*state {
-                    let mut stats = self.stats.write().await;
-                    stats.last_scan_start = Some(Local::now());
-                    stats.reset_counters();
-                    drop(stats);
-                    // State transition to Scanning
-                    debug!("-> ScanState::Scanning");
-                    *state = ScanState::Scanning(task::spawn(self.clone().task_fn_scan_dir()));
-                    Reply::OK
-                } else {
-                    Reply::NotIdle
-                }
-            }
+            Request::Run => self.run(ScanTarget::Root).await,
+            Request::Refresh(dirs) => self.run(ScanTarget::Refresh(dirs)).await,
+            Request::RunPath(dir) => self.run(ScanTarget::Path(dir, false)).await,
+            Request::Regenerate(dir) => self.run(ScanTarget::Path(dir, true)).await,
             Request::Stop => {
                 debug!("Request::Stop");
                 let mut state = self.state.write().await;
@@ -227,6 +320,7 @@ impl Scanner {
                     ScanState::Scanning(handle) => {
                         debug!("scan_dir handle.cancel");
                         handle.cancel().await;
+                        self.history_end("stopped", None).await;
                         debug!("-> ScanState::Idle");
                         *state = ScanState::Idle;
                         Reply::OK
@@ -236,6 +330,7 @@ impl Scanner {
                         update_handle.cancel().await;
                         debug!("indexer handle.stop");
                         indexer_handle.stop().await;
+                        self.history_end("stopped", None).await;
                         debug!("-> ScanState::Idle");
                         *state = ScanState::Idle;
                         Reply::OK
@@ -245,6 +340,97 @@ impl Scanner {
         }
     }
 
+    /// Make the thumbnail of an indexed media again, even if the file didn't change
+    pub async fn regenerate_thumb(&self, path: &str) -> Result<(), ScanError> {
+        let filepath = fullpath(&self.storage.root, path);
+        let sizes = self.storage.thumb_sizes.clone();
+        let thumbs = task::spawn_blocking(move || {
+            let media_exif = Exif::new(&filepath).ok();
+            make_thumb(&filepath, &media_exif, &sizes).map(|thumbs| thumbs.thumbs)
+        })
+        .await;
+        let thumbs = match thumbs {
+            Ok(thumbs) => thumbs,
+            Err(err) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO thumb_error (path, error, time) VALUES (?, ?, ?)",
+                )
+                .bind(path)
+                .bind(format!("{:?}", err))
+                .bind(Local::now().timestamp())
+                .execute(&self.storage.db)
+                .await?;
+                return Err(err.into());
+            }
+        };
+        {
+            let mut wtxn = self.storage.thumb_db_env.write_txn()?;
+            self.storage.thumb_put(&mut wtxn, path, &thumbs)?;
+            wtxn.commit()?;
+        }
+        sqlx::query("DELETE FROM thumb_error WHERE path = ?")
+            .bind(path)
+            .execute(&self.storage.db)
+            .await?;
+        Ok(())
+    }
+
+    // Record the start of a scan in the history
+    async fn history_start(&self) {
+        let res = sqlx::query("INSERT INTO scan (start_time, state) VALUES (?, 'running')")
+            .bind(Local::now().timestamp())
+            .execute(&self.storage.db)
+            .await;
+        match res {
+            Ok(res) => *self.scan_id.lock().await = Some(res.last_insert_rowid()),
+            Err(err) => error!("scan history start: {:?}", err),
+        }
+    }
+
+    // Record the end of the current scan in the history, with its final state
+    async fn history_end(&self, state: &str, error: Option<String>) {
+        let id = match self.scan_id.lock().await.take() {
+            Some(id) => id,
+            None => return,
+        };
+        let stats = *self.stats.read().await;
+        let res = sqlx::query(
+            "UPDATE scan SET end_time = ?, added = ?, updated = ?, deleted = ?, thumb_failures = ?,
+                state = ?, error = ?
+            WHERE id = ?",
+        )
+        .bind(Local::now().timestamp())
+        .bind(stats.scan_files_added as i64)
+        .bind(stats.scan_files_updated as i64)
+        .bind(stats.scan_files_deleted as i64)
+        .bind(stats.scan_thumb_failures as i64)
+        .bind(state)
+        .bind(error)
+        .bind(id)
+        .execute(&self.storage.db)
+        .await;
+        if let Err(err) = res {
+            error!("scan history end: {:?}", err);
+        }
+    }
+
+    /// Request a scan now and then every `interval`.  Scans due while the scanner is busy are
+    /// tried again shortly until it's idle.
+    pub async fn run_every(&self, interval: Duration) {
+        loop {
+            match self.request(Request::Run).await {
+                Reply::OK => {
+                    debug!("scheduled scan started");
+                    task::sleep(interval).await;
+                }
+                reply => {
+                    debug!("scheduled scan delayed: {:?}", reply);
+                    task::sleep(SCAN_RETRY_INTERVAL.min(interval)).await;
+                }
+            }
+        }
+    }
+
     pub async fn state(&self) -> State {
         (&*self.state.read().await).into()
     }
@@ -254,17 +440,90 @@ impl Scanner {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MediaType {
     Jpeg,
     JXL,
     Gif,
     Png,
     Webp,
+    Heif,
+    Avif,
+    Tiff,
+    Raw,
     Mp4,
+    Mkv,
+    Webm,
+    Mov,
+    Avi,
+    M4v,
 }
 
 use MediaType::*;
 
+impl MediaType {
+    pub const ALL: [MediaType; 15] = [
+        Jpeg, JXL, Gif, Png, Webp, Heif, Avif, Tiff, Raw, Mp4, Mkv, Webm, Mov, Avi, M4v,
+    ];
+
+    /// Name of the media type, as stored in the index and returned by the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Jpeg => "jpeg",
+            JXL => "jxl",
+            Gif => "gif",
+            Png => "png",
+            Webp => "webp",
+            Heif => "heif",
+            Avif => "avif",
+            Tiff => "tiff",
+            Raw => "raw",
+            Mp4 => "mp4",
+            Mkv => "mkv",
+            Webm => "webm",
+            Mov => "mov",
+            Avi => "avi",
+            M4v => "m4v",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Jpeg => "image/jpeg",
+            JXL => "image/jxl",
+            Gif => "image/gif",
+            Png => "image/png",
+            Webp => "image/webp",
+            Heif => "image/heif",
+            Avif => "image/avif",
+            Tiff => "image/tiff",
+            Raw => "image/x-dcraw",
+            Mp4 => "video/mp4",
+            Mkv => "video/x-matroska",
+            Webm => "video/webm",
+            Mov => "video/quicktime",
+            Avi => "video/x-msvideo",
+            M4v => "video/x-m4v",
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, Mp4 | Mkv | Webm | Mov | Avi | M4v)
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MediaType::ALL
+            .into_iter()
+            .find(|media_type| media_type.as_str() == s)
+            .ok_or(())
+    }
+}
+
 #[derive(Debug)]
 pub enum IsMediaError {
     PathEncoding,
@@ -286,7 +545,28 @@ pub fn is_media(path: &Path) -> Result<Option<MediaType>, IsMediaError> {
         "gif" => Gif,
         "webp" => Webp,
         "png" => Png,
+        "heic" => Heif,
+        "heif" => Heif,
+        "avif" => Avif,
+        "tif" => Tiff,
+        "tiff" => Tiff,
+        "arw" => Raw,
+        "cr2" => Raw,
+        "dng" => Raw,
+        "nef" => Raw,
+        "nrw" => Raw,
+        "orf" => Raw,
+        "pef" => Raw,
+        "raf" => Raw,
+        "rw2" => Raw,
+        "sr2" => Raw,
+        "srf" => Raw,
         "mp4" => Mp4,
+        "mkv" => Mkv,
+        "webm" => Webm,
+        "mov" => Mov,
+        "avi" => Avi,
+        "m4v" => M4v,
         _ => return Ok(None),
     };
     Ok(Some(ext))
@@ -321,22 +601,101 @@ impl fmt::Display for ThumbError {
 
 impl Error for ThumbError {}
 
-fn make_thumb<P>(filepath: P, _media_exif: &Option<Exif>) -> Result<Vec<u8>, ThumbError>
+// Thumbnails of a media in each size, with the dimensions of the media when they are known from
+// decoding it
+struct Thumbs {
+    thumbs: Vec<(u16, Vec<u8>)>,
+    dimensions: Option<(usize, usize)>,
+}
+
+fn make_thumb<P>(
+    filepath: P,
+    _media_exif: &Option<Exif>,
+    sizes: &[u16],
+) -> Result<Thumbs, ThumbError>
 where
     P: AsRef<Path>,
 {
     let filepath = filepath.as_ref();
-    let ext = filepath
-        .extension()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_lowercase();
-    let thumb = if ext == "mp4" || ext == "gif" {
-        ffmpeg::make_thumb(&*filepath.to_string_lossy())
+    let media_type = is_media(filepath)?;
+    // Biggest first, so that smaller thumbnails can be scaled down from bigger ones
+    let sizes: Vec<u16> = sizes.iter().rev().copied().collect();
+    // Videos and animations are thumbnailed from a representative frame
+    if media_type
+        .map(|t| t.is_video() || t == Gif)
+        .unwrap_or(false)
+    {
+        let thumbs = sizes
+            .iter()
+            .map(|size| {
+                Ok((
+                    *size,
+                    ffmpeg::make_thumb(&*filepath.to_string_lossy(), *size)?,
+                ))
+            })
+            .collect::<Result<_, ThumbError>>()?;
+        Ok(Thumbs {
+            thumbs,
+            dimensions: None,
+        })
     } else {
-        magick::make_thumb(&*filepath.to_string_lossy()).map_err(|err| ThumbError::Magick(err))
-    }?;
-    return Ok(thumb);
+        let path = filepath.to_string_lossy();
+        let preview = raw_preview(filepath)?;
+        let (dimensions, thumbs) = magick::make_thumbs(&magick_source(&path, &preview), &sizes)
+            .map_err(|err| ThumbError::Magick(err))?;
+        Ok(Thumbs {
+            thumbs,
+            dimensions: Some(dimensions),
+        })
+    }
+}
+
+/// Preview embedded in a camera RAW file, which is faster to decode than the RAW data and may
+/// be the only way to decode it.  Files whose preview can't be read are decoded in full.
+pub fn raw_preview(filepath: &Path) -> Result<Option<raw::Preview>, ThumbError> {
+    match is_media(filepath)? {
+        Some(Raw) => match raw::preview(filepath) {
+            Ok(preview) => Ok(preview),
+            Err(err) => {
+                warn!("raw preview {:?}: {:?}", filepath, err);
+                Ok(None)
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Source to read an image with ImageMagick: its preview if it has one, or the file
+pub fn magick_source<'a>(path: &'a str, preview: &'a Option<raw::Preview>) -> magick::Source<'a> {
+    match preview {
+        Some(preview) => magick::Source::Blob(&preview.jpeg, preview.orientation),
+        None => magick::Source::File(path),
+    }
+}
+
+// Fill the file size, dimensions and duration of a media entry.  Images take the dimensions
+// found when making their thumbnails, and videos and animations are probed.
+fn media_info(
+    filepath: &Path,
+    media: &mut tables::Image,
+    dimensions: Option<(usize, usize)>,
+) -> Result<(), ThumbError> {
+    media.size = Some(std::fs::metadata(filepath)?.len() as i64);
+    match is_media(filepath)? {
+        Some(media_type) if media_type.is_video() || media_type == Gif => {
+            let probe = ffmpeg::probe(&filepath.to_string_lossy())?;
+            media.width = probe.width.map(|v| v as i64);
+            media.height = probe.height.map(|v| v as i64);
+            if media_type.is_video() {
+                media.duration_ms = probe.duration.map(|v| (v * 1000.0) as i64);
+            }
+        }
+        _ => {
+            media.width = dimensions.map(|(width, _)| width as i64);
+            media.height = dimensions.map(|(_, height)| height as i64);
+        }
+    }
+    Ok(())
 }
 
 struct CompareResult<'a> {
@@ -348,7 +707,11 @@ struct CompareResult<'a> {
 #[derive(Debug)]
 struct MediaThumb {
     media: tables::Image,
-    thumb: Option<Vec<u8>>,
+    exif: Option<Exif>,
+    // Thumbnail of each size
+    thumbs: Option<Vec<(u16, Vec<u8>)>>,
+    // Why the thumbnail could not be made
+    thumb_error: Option<String>,
 }
 
 impl MediaThumb {
@@ -360,8 +723,14 @@ impl MediaThumb {
                 timestamp: 0,
                 mtime: mtime,
                 dir: dir.to_string_lossy().to_string(),
+                media_type: is_media(Path::new(name))
+                    .unwrap_or(None)
+                    .map(|media_type| media_type.as_str().to_string()),
+                ..Default::default()
             },
-            thumb: None,
+            exif: None,
+            thumbs: None,
+            thumb_error: None,
         }
     }
 }
@@ -484,6 +853,8 @@ struct Indexer {
     stats: Arc<RwLock<Stats>>,
     thumbs_req: (Sender<IndexRequest>, Receiver<IndexRequest>),
     // thumbs_res: (Sender<IndexRequest>, Receiver<IndexRequest>),
+    // Index all the scanned files again, even if they didn't change
+    force: bool,
 }
 
 type Tx = sqlx::Transaction<'static, sqlx::Sqlite>;
@@ -595,6 +966,7 @@ impl Indexer {
         state: Storage,
         stats: Arc<RwLock<Stats>>,
         n_threads: usize,
+        force: bool,
     ) -> (Self, IndexerHandle) {
         let thumbs_req = channel::bounded(n_threads);
         let thumbs_res = channel::bounded(n_threads);
@@ -630,6 +1002,7 @@ impl Indexer {
                 stats,
                 thumbs_req: thumbs_req.clone(),
                 // thumbs_res: thumbs_res.clone(),
+                force,
             },
             IndexerHandle {
                 stop,
@@ -664,17 +1037,26 @@ impl Indexer {
                     .map(|e| e.date_time_original)
                     .unwrap_or(None) // This can happen when a file doesn't have EXIF data.
                     .unwrap_or(entry.media.mtime);
-                entry.thumb = match make_thumb(&path, &media_exif) {
-                    Ok(thumb) => Some(thumb),
+                let mut dimensions = None;
+                entry.thumbs = match make_thumb(&path, &media_exif, &state.thumb_sizes) {
+                    Ok(thumbs) => {
+                        dimensions = thumbs.dimensions;
+                        Some(thumbs.thumbs)
+                    }
                     Err(err) => {
                         error!(
                             "cannot make thumb for {:?}: {:?}",
                             fullpath(&state.root, &entry.media.path),
                             err
                         );
+                        entry.thumb_error = Some(format!("{:?}", err));
                         None
                     }
                 };
+                entry.exif = media_exif;
+                if let Err(err) = media_info(&path, &mut entry.media, dimensions) {
+                    error!("cannot get media info for {:?}: {:?}", path, err);
+                }
             }
             match thumbs_res_sender.send(res).await {
                 Ok(_) => {}
@@ -714,8 +1096,8 @@ impl Indexer {
         {
             let mut wtxn = state.thumb_db_env.write_txn()?;
             for entry in res.new.iter().chain(res.update.iter()) {
-                if let Some(thumb) = &entry.thumb {
-                    state.thumb_db.put(&mut wtxn, &entry.media.path, thumb)?;
+                if let Some(thumbs) = &entry.thumbs {
+                    state.thumb_put(&mut wtxn, &entry.media.path, thumbs)?;
                 }
             }
             wtxn.commit()?;
@@ -728,41 +1110,151 @@ impl Indexer {
             }
             let media = &entry.media;
             sqlx::query(
-                "INSERT INTO image (path, name, dir, mtime, timestamp) VALUES (?, ?, ?, ?, ?)",
+                "INSERT INTO image (path, name, dir, mtime, timestamp, size, width, height, duration_ms,
+                    media_type)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             )
             .bind(&media.path)
             .bind(&media.name)
             .bind(&media.dir)
             .bind(media.mtime)
             .bind(media.timestamp)
+            .bind(media.size)
+            .bind(media.width)
+            .bind(media.height)
+            .bind(media.duration_ms)
+            .bind(&media.media_type)
             .execute(&mut batch)
             .await?;
+            sqlx::query("INSERT INTO search (rowid, name, dir) SELECT id, name, dir FROM image WHERE path = ?")
+                .bind(&media.path)
+                .execute(&mut batch)
+                .await?;
         }
         for entry in &res.update {
             if *stop.read().await {
                 return Ok(());
             }
             let media = &entry.media;
-            sqlx::query("UPDATE image SET mtime = ?, timestamp = ? WHERE path = ?")
-                .bind(media.mtime)
-                .bind(media.timestamp)
-                .bind(&media.path)
+            sqlx::query(
+                "UPDATE image SET mtime = ?, timestamp = ?, size = ?, width = ?, height = ?,
+                    duration_ms = ?
+                WHERE path = ?",
+            )
+            .bind(media.mtime)
+            .bind(media.timestamp)
+            .bind(media.size)
+            .bind(media.width)
+            .bind(media.height)
+            .bind(media.duration_ms)
+            .bind(&media.path)
+            .execute(&mut batch)
+            .await?;
+        }
+        for entry in res.new.iter().chain(res.update.iter()) {
+            if *stop.read().await {
+                return Ok(());
+            }
+            sqlx::query("DELETE FROM reindex WHERE path = ?")
+                .bind(&entry.media.path)
                 .execute(&mut batch)
                 .await?;
+            match &entry.thumb_error {
+                Some(error) => {
+                    sqlx::query(
+                        "INSERT OR REPLACE INTO thumb_error (path, error, time) VALUES (?, ?, ?)",
+                    )
+                    .bind(&entry.media.path)
+                    .bind(error)
+                    .bind(Local::now().timestamp())
+                    .execute(&mut batch)
+                    .await?;
+                }
+                None => {
+                    sqlx::query("DELETE FROM thumb_error WHERE path = ?")
+                        .bind(&entry.media.path)
+                        .execute(&mut batch)
+                        .await?;
+                }
+            }
+            let exif = match &entry.exif {
+                Some(exif) => exif,
+                None => {
+                    sqlx::query("DELETE FROM exif WHERE path = ?")
+                        .bind(&entry.media.path)
+                        .execute(&mut batch)
+                        .await?;
+                    sqlx::query(
+                        "DELETE FROM geo WHERE id IN (SELECT id FROM image WHERE path = ?)",
+                    )
+                    .bind(&entry.media.path)
+                    .execute(&mut batch)
+                    .await?;
+                    continue;
+                }
+            };
+            sqlx::query(
+                "INSERT OR REPLACE INTO exif (path, make, model, lens, exposure_time, aperture, iso,
+                    focal_length, flash, latitude, longitude, altitude)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&entry.media.path)
+            .bind(&exif.make)
+            .bind(&exif.model)
+            .bind(&exif.lens)
+            .bind(exif.exposure_time)
+            .bind(exif.aperture)
+            .bind(exif.iso)
+            .bind(exif.focal_length)
+            .bind(exif.flash)
+            .bind(exif.gps.as_ref().map(|gps| gps.latitude))
+            .bind(exif.gps.as_ref().map(|gps| gps.longitude))
+            .bind(exif.gps.as_ref().and_then(|gps| gps.altitude))
+            .execute(&mut batch)
+            .await?;
+            sqlx::query("DELETE FROM geo WHERE id IN (SELECT id FROM image WHERE path = ?)")
+                .bind(&entry.media.path)
+                .execute(&mut batch)
+                .await?;
+            if let Some(gps) = &exif.gps {
+                sqlx::query(
+                    "INSERT INTO geo (id, min_lat, max_lat, min_lon, max_lon)
+                    SELECT id, ?1, ?1, ?2, ?2 FROM image WHERE path = ?3",
+                )
+                .bind(gps.latitude)
+                .bind(gps.longitude)
+                .bind(&entry.media.path)
+                .execute(&mut batch)
+                .await?;
+            }
         }
         batch.commit().await?;
-        stats.write().await.scan_files_count += res.new.len() + res.update.len();
+        let mut stats = stats.write().await;
+        stats.scan_files_count += res.new.len() + res.update.len();
+        stats.scan_files_added += res.new.len();
+        stats.scan_files_updated += res.update.len();
+        stats.scan_thumb_failures += res
+            .new
+            .iter()
+            .chain(res.update.iter())
+            .filter(|entry| entry.thumbs.is_none())
+            .count();
         Ok(())
     }
 
-    pub async fn update(&self, scan_dir: &ScanDir) -> Result<(), ScanError> {
-        let parent = &Path::new("/");
+    // Update the index of `scan_dir`, found in the `parent` directory.  The root is scanned as "."
+    // in "/".
+    pub async fn update(&self, parent: &Path, scan_dir: &ScanDir) -> Result<(), ScanError> {
         self.stats.write().await.scan_folders_count += 1;
         self.update_inner(parent, scan_dir).await
     }
 
     #[async_recursion]
     async fn update_inner(self: &Self, parent: &Path, scan_dir: &ScanDir) -> Result<(), ScanError> {
+        if !scan_dir.scanned {
+            // Contents left untouched by a shallow scan
+            return Ok(());
+        }
         let path = subpath(parent, &scan_dir.name);
         let path_string = &*path.to_string_lossy();
         // println!("path_string = {:?}", path_string);
@@ -811,10 +1303,37 @@ impl Indexer {
         for name in &subdirs_cmp.del {
             let del_path = subpath(&path, name);
             let del_path_str = &*del_path.to_string_lossy();
-            sqlx::query("DELETE FROM image WHERE dir LIKE ?")
+            sqlx::query(
+                "DELETE FROM search WHERE rowid IN (SELECT id FROM image WHERE dir LIKE ?)",
+            )
+            .bind(format!("{}%", del_path_str))
+            .execute(&mut batch)
+            .await?;
+            sqlx::query("DELETE FROM geo WHERE id IN (SELECT id FROM image WHERE dir LIKE ?)")
                 .bind(format!("{}%", del_path_str))
                 .execute(&mut batch)
                 .await?;
+            sqlx::query("DELETE FROM exif WHERE path IN (SELECT path FROM image WHERE dir LIKE ?)")
+                .bind(format!("{}%", del_path_str))
+                .execute(&mut batch)
+                .await?;
+            sqlx::query(
+                "DELETE FROM thumb_error WHERE path IN (SELECT path FROM image WHERE dir LIKE ?)",
+            )
+            .bind(format!("{}%", del_path_str))
+            .execute(&mut batch)
+            .await?;
+            sqlx::query(
+                "DELETE FROM reindex WHERE path IN (SELECT path FROM image WHERE dir LIKE ?)",
+            )
+            .bind(format!("{}%", del_path_str))
+            .execute(&mut batch)
+            .await?;
+            let deleted = sqlx::query("DELETE FROM image WHERE dir LIKE ?")
+                .bind(format!("{}%", del_path_str))
+                .execute(&mut batch)
+                .await?;
+            self.stats.write().await.scan_files_deleted += deleted.rows_affected() as usize;
             sqlx::query("DELETE FROM folder WHERE dir LIKE ?")
                 .bind(format!("{}%", del_path_str))
                 .execute(&mut batch)
@@ -841,6 +1360,13 @@ impl Indexer {
             }
             wtxn.commit()?;
         }
+        if let Some(cache) = &self.state.cache {
+            for name in &subdirs_cmp.del {
+                cache
+                    .invalidate(&subpath(&path, name).to_string_lossy())
+                    .await;
+            }
+        }
 
         self.stats.write().await.scan_folders_count += scan_dir.dirs.len();
 
@@ -860,10 +1386,36 @@ impl Indexer {
             .iter()
             .map(|v| (v.name.as_str(), v.mtime))
             .collect();
-        let files_cmp = compare_entries(&scan_files, &db_files);
+        let mut files_cmp = compare_entries(&scan_files, &db_files);
+        if self.force {
+            files_cmp.update = scan_files
+                .keys()
+                .filter(|name| !files_cmp.new.contains(name))
+                .copied()
+                .collect();
+        }
+        // Retry unchanged files whose thumbnail failed, and index again the ones indexed by an
+        // older version, which have no size
+        let retry: Vec<(String,)> = sqlx::query_as(
+            "SELECT name FROM image WHERE dir = ? AND (size IS NULL
+                OR path IN (SELECT path FROM thumb_error UNION SELECT path FROM reindex))",
+        )
+        .bind(path.to_string_lossy().to_string())
+        .fetch_all(&self.state.db)
+        .await?;
+        for (name,) in &retry {
+            if let Some((name, _)) = scan_files.get_key_value(name.as_str()) {
+                if !files_cmp.update.contains(name) {
+                    files_cmp.update.push(name);
+                }
+            }
+        }
+        let mut stats = self.stats.write().await;
         // Substract unchanged + deleted files
-        self.stats.write().await.scan_files_count +=
+        stats.scan_files_count +=
             scan_dir.files.len() - files_cmp.new.len() - files_cmp.update.len();
+        stats.scan_files_deleted += files_cmp.del.len();
+        drop(stats);
 
         // new_files = scan_dir.files - db_files
         // update_files = scan_dir.files & db_files WHERE scandir_file.mtime != db_file.mtime
@@ -872,6 +1424,26 @@ impl Indexer {
         // del_files -> del thumb + del SQL in image
         let mut batch = Batch::new(MAX_SQL_TX_SIZE, &self.state.db).await?;
         for name in &files_cmp.del {
+            sqlx::query("DELETE FROM search WHERE rowid IN (SELECT id FROM image WHERE path = ?)")
+                .bind(&*subpath(&path, name).to_string_lossy())
+                .execute(&mut batch)
+                .await?;
+            sqlx::query("DELETE FROM geo WHERE id IN (SELECT id FROM image WHERE path = ?)")
+                .bind(&*subpath(&path, name).to_string_lossy())
+                .execute(&mut batch)
+                .await?;
+            sqlx::query("DELETE FROM exif WHERE path = ?")
+                .bind(&*subpath(&path, name).to_string_lossy())
+                .execute(&mut batch)
+                .await?;
+            sqlx::query("DELETE FROM thumb_error WHERE path = ?")
+                .bind(&*subpath(&path, name).to_string_lossy())
+                .execute(&mut batch)
+                .await?;
+            sqlx::query("DELETE FROM reindex WHERE path = ?")
+                .bind(&*subpath(&path, name).to_string_lossy())
+                .execute(&mut batch)
+                .await?;
             sqlx::query("DELETE FROM image WHERE path = ?")
                 .bind(&*subpath(&path, name).to_string_lossy())
                 .execute(&mut batch)
@@ -883,11 +1455,17 @@ impl Indexer {
             let mut wtxn = self.state.thumb_db_env.write_txn()?;
             for name in &files_cmp.del {
                 self.state
-                    .thumb_db
-                    .delete(&mut wtxn, &*subpath(&path, name).to_string_lossy())?;
+                    .thumb_delete(&mut wtxn, &*subpath(&path, name).to_string_lossy())?;
             }
             wtxn.commit()?;
         }
+        if let Some(cache) = &self.state.cache {
+            for name in files_cmp.del.iter().chain(files_cmp.update.iter()) {
+                cache
+                    .invalidate(&subpath(&path, name).to_string_lossy())
+                    .await;
+            }
+        }
 
         // new_files -> gen thumb + set thumb + create SQL in image
         // update_files -> gen thumb + set thumb + update SQL in image
@@ -942,94 +1520,293 @@ struct ScanDir {
     mtime: i64,
     dirs: Vec<ScanDir>,
     files: Vec<ScanFile>,
+    // False for subdirectories left out of a shallow scan, whose contents are not compared with
+    // the index.
+    scanned: bool,
+}
+
+// Options shared by all the entries of a directory scan
+struct ScanOpts {
+    // Follow symbolic links to folders and media.  Links that point outside of `root` are skipped.
+    follow_symlinks: bool,
+    // Canonical path of the scanned directory
+    root: PathBuf,
 }
 
-async fn scan_dir(mut stats: Arc<RwLock<Stats>>, dir: PathBuf) -> Result<ScanDir, ScanError> {
+// Identifies a directory by its device and inode numbers, to detect symbolic link loops
+type DirId = (u64, u64);
+
+fn dir_id(metadata: &async_std::fs::Metadata) -> DirId {
+    (metadata.dev(), metadata.ino())
+}
+
+async fn scan_dir(
+    mut stats: Arc<RwLock<Stats>>,
+    dir: PathBuf,
+    follow_symlinks: bool,
+) -> Result<ScanDir, ScanError> {
     let mut stats_lock = stats.write().await;
     stats_lock.scan_folders_total = 0;
     stats_lock.scan_files_total = 0;
     drop(stats_lock);
-    let (_, scan_dir) = scan_dir_inner(&mut stats, &dir, ".".to_string(), 0).await?;
+    let opts = ScanOpts {
+        follow_symlinks,
+        root: dir.canonicalize().map_err(|e| (dir.clone(), e))?,
+    };
+    let metadata = async_std::fs::metadata(&dir)
+        .await
+        .map_err(|e| (dir.clone(), e))?;
+    let mut ancestors = vec![dir_id(&metadata)];
+    let (_, scan_dir) = scan_dir_inner(
+        &mut stats,
+        &opts,
+        &mut ancestors,
+        &dir,
+        ".".to_string(),
+        0,
+        &HashSet::new(),
+    )
+    .await?;
     Ok(scan_dir)
 }
 
+// Scan directories, relative to the root.  Each directory that is not indexed or doesn't exist is
+// replaced by its closest ancestor that is, which is scanned shallowly.  In a shallow scan,
+// subdirectories that are already indexed are not scanned, while new ones are scanned fully.
+// Every scanned directory is returned with the path of its parent.
+async fn scan_dirs(
+    storage: &Storage,
+    mut stats: Arc<RwLock<Stats>>,
+    dirs: Vec<PathBuf>,
+    follow_symlinks: bool,
+    shallow: bool,
+) -> Result<Vec<(PathBuf, ScanDir)>, ScanError> {
+    let mut stats_lock = stats.write().await;
+    stats_lock.scan_folders_total = 0;
+    stats_lock.scan_files_total = 0;
+    drop(stats_lock);
+    let root = &storage.root;
+    let opts = ScanOpts {
+        follow_symlinks,
+        root: root.canonicalize().map_err(|e| (root.clone(), e))?,
+    };
+
+    // Directories to scan, and whether the scan is shallow
+    let mut targets: BTreeMap<PathBuf, bool> = BTreeMap::new();
+    for mut dir in dirs {
+        let mut dir_shallow = shallow;
+        loop {
+            let indexed: Option<(String,)> =
+                sqlx::query_as("SELECT path FROM folder WHERE path = ?")
+                    .bind(&*dir.to_string_lossy())
+                    .fetch_optional(&storage.db)
+                    .await?;
+            if (indexed.is_some() && fullpath(root, &dir).is_dir()) || !dir.pop() {
+                break;
+            }
+            dir_shallow = true;
+        }
+        let target_shallow = targets.entry(dir).or_insert(dir_shallow);
+        *target_shallow = *target_shallow && dir_shallow;
+    }
+
+    let mut scans = Vec::with_capacity(targets.len());
+    for (dir, shallow) in targets {
+        let known: HashSet<String> = if shallow {
+            let known: Vec<(String,)> = sqlx::query_as("SELECT name FROM folder WHERE dir = ?")
+                .bind(&*dir.to_string_lossy())
+                .fetch_all(&storage.db)
+                .await?;
+            known.into_iter().map(|(name,)| name).collect()
+        } else {
+            HashSet::new()
+        };
+        // Directories from the root down to `dir`, for loop detection
+        let mut ancestors = Vec::new();
+        for ancestor in dir.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            let path = fullpath(root, ancestor);
+            let metadata = async_std::fs::metadata(&path)
+                .await
+                .map_err(|e| (path.clone(), e))?;
+            ancestors.push(dir_id(&metadata));
+        }
+        let (parent, name) = match (dir.parent(), dir.file_name()) {
+            (Some(parent), Some(name)) => {
+                (parent.to_path_buf(), name.to_string_lossy().to_string())
+            }
+            _ => (PathBuf::from("/"), ".".to_string()),
+        };
+        let (_, scan_dir) = scan_dir_inner(
+            &mut stats,
+            &opts,
+            &mut ancestors,
+            &fullpath(root, &dir),
+            name,
+            0,
+            &known,
+        )
+        .await?;
+        scans.push((parent, scan_dir));
+    }
+    Ok(scans)
+}
+
 enum Entry {
-    Dir(PathBuf, String, i64),
+    Dir(PathBuf, String, i64, DirId),
     File(String, i64),
 }
 
+// Classify a directory entry as a subdirectory, a media file, or something to skip.  When the
+// entry is a followed symbolic link, its path is returned as well.
+async fn scan_entry(
+    follow_symlinks: bool,
+    entry_res: io::Result<async_std::fs::DirEntry>,
+) -> Option<(Entry, Option<PathBuf>)> {
+    let entry = match entry_res {
+        Ok(entry) => entry,
+        Err(err) => {
+            error!("Reading DirEntry: {:?}", err);
+            return None;
+        }
+    };
+    let entry_name = entry.file_name().to_string_lossy().to_string();
+    if entry_name.starts_with(".") {
+        // Skip hidden entries
+        return None;
+    }
+    let mut metadata = match entry.metadata().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Reading entry metadata {:?}: {:?}", entry.path(), e);
+            return None;
+        }
+    };
+    let symlink = metadata.file_type().is_symlink();
+    if symlink {
+        if !follow_symlinks {
+            return None;
+        }
+        metadata = match async_std::fs::metadata(entry.path()).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!(
+                    "Reading symlink target metadata {:?}: {:?}",
+                    entry.path(),
+                    e
+                );
+                return None;
+            }
+        };
+    }
+    let modified = match metadata.modified() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Reading metadata modified {:?}: {:?}", entry.path(), e);
+            return None;
+        }
+    };
+    let path: PathBuf = entry.path().into();
+    let link = if symlink { Some(path.clone()) } else { None };
+    let entry_mtime = modified
+        .duration_since(time::UNIX_EPOCH)
+        .expect("modified.duration_since")
+        .as_secs() as i64;
+    if metadata.is_dir() {
+        let id = dir_id(&metadata);
+        Some((Entry::Dir(path, entry_name, entry_mtime, id), link))
+    } else if metadata.is_file() && is_media(&path).unwrap_or(None).is_some() {
+        Some((Entry::File(entry_name, entry_mtime), link))
+    } else {
+        None
+    }
+}
+
+// Check that a symbolic link resolves to a path inside of the scanned root.
+async fn link_in_root(opts: &ScanOpts, link: &Path) -> bool {
+    let target = match async_std::fs::canonicalize(link).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Resolving symlink {:?}: {:?}", link, e);
+            return false;
+        }
+    };
+    if !target.starts_with(&opts.root) {
+        warn!(
+            "Skipping symlink {:?} to {:?} outside of root",
+            link, target
+        );
+        return false;
+    }
+    true
+}
+
 // The returned bool is true when this dir has media.
 // TODO: Find a more efficient parallelization strategy
 #[async_recursion]
 async fn scan_dir_inner(
     mut stats: &mut Arc<RwLock<Stats>>,
+    opts: &ScanOpts,
+    ancestors: &mut Vec<DirId>,
     dir: &Path,
     name: String,
     mtime: i64,
+    // Subdirectories to leave unscanned
+    known: &HashSet<String>,
 ) -> Result<(bool, ScanDir), ScanError> {
-    let entries: Vec<Option<Entry>> = from_stream(
+    let follow_symlinks = opts.follow_symlinks;
+    let entries: Vec<Option<(Entry, Option<PathBuf>)>> = from_stream(
         async_std::fs::read_dir(dir)
             .await
             .map_err(|e| (dir.to_path_buf(), e))?,
     )
     .into_par_stream()
-    .map(|entry_res| async {
-        let entry = match entry_res {
-            Ok(entry) => entry,
-            Err(err) => {
-                error!("Reading DirEntry: {:?}", err);
-                return None;
-            }
-        };
-        let entry_name = entry.file_name().to_string_lossy().to_string();
-        if entry_name.starts_with(".") {
-            // Skip hidden entries
-            return None;
-        }
-        let metadata = match entry.metadata().await {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Reading entry metadata {:?}: {:?}", entry.path(), e);
-                return None;
-            }
-        };
-        let modified = match metadata.modified() {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Reading metadata modified {:?}: {:?}", entry.path(), e);
-                return None;
-            }
-        };
-        let path = entry.path();
-        let entry_mtime = modified
-            .duration_since(time::UNIX_EPOCH)
-            .expect("modified.duration_since")
-            .as_secs() as i64;
-        if metadata.is_dir() {
-            Some(Entry::Dir(path.into(), entry_name, entry_mtime))
-        } else if metadata.is_file() && is_media(&path.as_ref()).unwrap_or(None).is_some() {
-            Some(Entry::File(entry_name, entry_mtime))
-        } else {
-            None
-        }
-    })
+    .map(move |entry_res| scan_entry(follow_symlinks, entry_res))
     .collect()
     .await;
 
-    let mut subdirs: Vec<(PathBuf, String, i64)> = Vec::new();
+    let mut subdirs: Vec<(PathBuf, String, i64, DirId)> = Vec::new();
     let mut files: Vec<ScanFile> = Vec::new();
-    entries.into_iter().for_each(|opt| {
-        if let Some(entry) = opt {
-            match entry {
-                Entry::Dir(path, name, mtime) => subdirs.push((path, name, mtime)),
-                Entry::File(name, mtime) => files.push(ScanFile { name, mtime }),
+    for (entry, link) in entries.into_iter().flatten() {
+        if let Some(link) = link {
+            if !link_in_root(opts, &link).await {
+                continue;
             }
         }
-    });
+        match entry {
+            Entry::Dir(path, name, mtime, id) => subdirs.push((path, name, mtime, id)),
+            Entry::File(name, mtime) => files.push(ScanFile { name, mtime }),
+        }
+    }
     stats.write().await.scan_files_total += files.len();
     let mut dirs = Vec::with_capacity(subdirs.len());
-    for (subdir, name, mtime) in subdirs {
-        let (dir_has_media, dir) = scan_dir_inner(&mut stats, &subdir, name, mtime).await?;
+    for (subdir, name, mtime, id) in subdirs {
+        if known.contains(&name) {
+            dirs.push(ScanDir {
+                name,
+                mtime,
+                dirs: Vec::new(),
+                files: Vec::new(),
+                scanned: false,
+            });
+            continue;
+        }
+        if ancestors.contains(&id) {
+            warn!("Skipping {:?}: symlink loop", subdir);
+            continue;
+        }
+        ancestors.push(id);
+        let res = scan_dir_inner(
+            &mut stats,
+            opts,
+            ancestors,
+            &subdir,
+            name,
+            mtime,
+            &HashSet::new(),
+        )
+        .await;
+        ancestors.pop();
+        let (dir_has_media, dir) = res?;
         if dir_has_media {
             dirs.push(dir);
         }
@@ -1048,6 +1825,7 @@ async fn scan_dir_inner(
             mtime,
             dirs,
             files,
+            scanned: true,
         },
     ))
 }
@@ -1071,6 +1849,47 @@ mod test {
     }
     */
 
+    #[async_std::test]
+    async fn test_scan_dir_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = TempDir::new().expect("new temp_dir");
+        let root = temp_dir.path().join("root");
+        let outside = temp_dir.path().join("outside");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("a/b/image.jpg"), b"").unwrap();
+        std::fs::write(outside.join("outside.jpg"), b"").unwrap();
+        symlink("..", root.join("a/b/loop")).unwrap();
+        symlink("a/b", root.join("link")).unwrap();
+        symlink("a/b/image.jpg", root.join("link.jpg")).unwrap();
+        symlink(&outside, root.join("outside")).unwrap();
+        symlink(outside.join("outside.jpg"), root.join("outside.jpg")).unwrap();
+
+        fn paths(dir: &ScanDir, prefix: &str, out: &mut Vec<String>) {
+            for file in &dir.files {
+                out.push(format!("{}/{}", prefix, file.name));
+            }
+            for subdir in &dir.dirs {
+                paths(subdir, &format!("{}/{}", prefix, subdir.name), out);
+            }
+        }
+
+        for (follow_symlinks, expected) in [
+            (false, vec!["/a/b/image.jpg"]),
+            (true, vec!["/a/b/image.jpg", "/link.jpg", "/link/image.jpg"]),
+        ] {
+            let stats = Arc::new(RwLock::new(Stats::new()));
+            let scan_dir = scan_dir(stats, root.clone(), follow_symlinks)
+                .await
+                .expect("scan_dir");
+            let mut found = Vec::new();
+            paths(&scan_dir, "", &mut found);
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
     fn hash(input: &[u8]) -> Vec<u8> {
         let mut hasher = Sha3_256::new();
         hasher.update(input);
@@ -1087,15 +1906,18 @@ mod test {
     async fn do_scan(cfg: StateConfig<'_>) -> ScanResult {
         let state = Storage::new(&cfg).await.expect("Storage::new");
         let stats = Arc::new(RwLock::new(Stats::new()));
-        let scan_dir = scan_dir(stats.clone(), cfg.root.clone())
+        let scan_dir = scan_dir(stats.clone(), cfg.root.clone(), false)
             .await
             .expect("scan_dir");
         // println!("scan_dir: {:?}", scan_dir);
-        let (indexer, mut indexer_handle) = Indexer::start(state.clone(), stats.clone(), 4);
-        indexer.update(&scan_dir).await.expect("indexer.update");
+        let (indexer, mut indexer_handle) = Indexer::start(state.clone(), stats.clone(), 4, false);
+        indexer
+            .update(Path::new("/"), &scan_dir)
+            .await
+            .expect("indexer.update");
         indexer_handle.wait_stop().await;
         /*
-        let mut scanner = Scanner::new(state.clone(), 4);
+        let mut scanner = Scanner::new(state.clone(), 4, false);
         scanner.request(Request::Run).await;
         loop {
             let state = scanner.state().await;
@@ -1162,6 +1984,7 @@ mod test {
                 dir: $dir.to_string(),
                 mtime: $mtime as i64,
                 timestamp: $ts as i64,
+                ..Default::default()
             }
         };
     }
@@ -1306,12 +2129,15 @@ mod test {
         };
         let state = Storage::new(&cfg).await.expect("Storage::new");
         let stats = Arc::new(RwLock::new(Stats::new()));
-        let scan_dir = scan_dir(stats.clone(), cfg.root.clone())
+        let scan_dir = scan_dir(stats.clone(), cfg.root.clone(), false)
             .await
             .expect("scan_dir");
         println!("scan_dir complete");
-        let (indexer, mut indexer_handle) = Indexer::start(state.clone(), stats, 12);
-        indexer.update(&scan_dir).await.expect("indexer.update");
+        let (indexer, mut indexer_handle) = Indexer::start(state.clone(), stats, 12, false);
+        indexer
+            .update(Path::new("/"), &scan_dir)
+            .await
+            .expect("indexer.update");
         indexer_handle.wait_stop().await;
     }
     */
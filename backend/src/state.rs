@@ -2,18 +2,23 @@ use async_std::sync::Arc;
 use heed::types::*;
 use log::LevelFilter;
 use sqlx::migrate::MigrateDatabase;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
-use sqlx::ConnectOptions;
+use sqlx::query::QueryAs;
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqlitePool};
+use sqlx::{ConnectOptions, Connection, Sqlite};
 use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
-use crate::models::{queries, views};
-use crate::scanner::Scanner;
+use crate::cache::DiskCache;
+use crate::models::{queries, tables, views};
+use crate::scanner::{self, MediaType, Scanner};
+
+// Cells at this zoom level are already smaller than the precision of the spatial index.
+const MAX_GEO_ZOOM: u8 = 24;
 
 #[allow(unused)]
 #[derive(Debug)]
@@ -36,12 +41,62 @@ impl fmt::Display for ThumbError {
 
 impl Error for ThumbError {}
 
+/// Conditions on the listed media in addition to their folder.
+#[derive(Debug, Default)]
+pub struct MediaFilter {
+    /// Only media taken at or after this timestamp
+    pub from: Option<i64>,
+    /// Only media taken before this timestamp
+    pub to: Option<i64>,
+    /// Only media of one of these types
+    pub media_types: Option<Vec<MediaType>>,
+}
+
+impl MediaFilter {
+    // SQL conditions to append to the WHERE clause of a query on the image table
+    fn sql(&self) -> String {
+        let mut sql = String::new();
+        if self.from.is_some() {
+            sql.push_str(" AND image.timestamp >= ?");
+        }
+        if self.to.is_some() {
+            sql.push_str(" AND image.timestamp < ?");
+        }
+        if let Some(media_types) = &self.media_types {
+            let params = vec!["?"; media_types.len()].join(", ");
+            sql.push_str(&format!(" AND image.media_type IN ({})", params));
+        }
+        sql
+    }
+
+    // Bind the parameters of the conditions returned by `sql`
+    fn bind<'q, O>(
+        &self,
+        mut query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+    ) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        if let Some(from) = self.from {
+            query = query.bind(from);
+        }
+        if let Some(to) = self.to {
+            query = query.bind(to);
+        }
+        for media_type in self.media_types.iter().flatten() {
+            query = query.bind(media_type.as_str());
+        }
+        query
+    }
+}
+
 #[derive(Clone)]
 pub struct Storage {
     pub root: PathBuf,
     pub db: SqlitePool,
     pub thumb_db: heed::Database<Str, ByteSlice>,
     pub thumb_db_env: heed::Env,
+    /// Sizes of the thumbnails made for each media, in ascending order
+    pub thumb_sizes: Vec<u16>,
+    /// Cache of resized and converted images
+    pub cache: Option<Arc<DiskCache>>,
     pub page_size: usize,
 }
 
@@ -60,17 +115,27 @@ impl<'a> Storage {
             .open(cfg.path_mdb)?;
         let thumb_db: heed::Database<Str, ByteSlice> = env.create_database(None)?;
 
+        let mut thumb_sizes = cfg.thumb_sizes.clone();
+        thumb_sizes.sort();
+        thumb_sizes.dedup();
+        let cache = match cfg.cache_dir {
+            Some(dir) => Some(Arc::new(DiskCache::new(dir.clone(), cfg.cache_size).await?)),
+            None => None,
+        };
         let mut storage = Self {
             root: cfg.root.clone(),
             db,
             thumb_db,
             thumb_db_env: env,
+            thumb_sizes,
+            cache,
             page_size: cfg.page_size,
         };
         storage.init().await?;
         Ok(storage)
     }
     async fn init(&mut self) -> Result<(), sqlx::Error> {
+        migrate_image_id(&self.db).await?;
         let mut tx = self.db.begin().await?;
         sqlx::query(
             r#"
@@ -86,22 +151,152 @@ impl<'a> Storage {
         .execute(&mut *tx)
         .await?;
 
+        sqlx::query(&image_table_sql("image"))
+            .execute(&mut *tx)
+            .await?;
+        // Columns missing in databases created by older versions
+        add_column(&mut tx, "image", "size", "INTEGER").await?;
+        add_column(&mut tx, "image", "width", "INTEGER").await?;
+        add_column(&mut tx, "image", "height", "INTEGER").await?;
+        add_column(&mut tx, "image", "duration_ms", "INTEGER").await?;
+        add_column(&mut tx, "image", "media_type", "TEXT").await?;
+        // Fill the media type of entries indexed before it was stored
+        let untyped: Vec<(String, String)> =
+            sqlx::query_as("SELECT path, name FROM image WHERE media_type IS NULL")
+                .fetch_all(&mut *tx)
+                .await?;
+        for (path, name) in untyped {
+            let media_type = scanner::is_media(Path::new(&name)).unwrap_or(None);
+            sqlx::query("UPDATE image SET media_type = ? WHERE path = ?")
+                .bind(media_type.map(|media_type| media_type.as_str()))
+                .bind(&path)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS dir_index ON image (dir);")
+            .execute(&mut *tx)
+            .await?;
+
+        // Media indexed by an older version, indexed again on the next scan to fill the data it
+        // didn't store
         sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS image (
+            CREATE TABLE IF NOT EXISTS reindex (
                 path            TEXT PRIMARY KEY,
-                name            TEXT NOT NULL,
-                dir             TEXT NOT NULL,
-                mtime           INTEGER NOT NULL,
-                timestamp       INTEGER NOT NULL,
-                FOREIGN KEY(dir)  REFERENCES folder(path)
+                FOREIGN KEY(path) REFERENCES image(path)
             );
             "#,
         )
         .execute(&mut *tx)
         .await?;
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS dir_index ON image (dir);")
+        let exif_exists = table_exists(&mut tx, "exif").await?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS exif (
+                path            TEXT PRIMARY KEY,
+                make            TEXT,
+                model           TEXT,
+                lens            TEXT,
+                exposure_time   REAL,
+                aperture        REAL,
+                iso             INTEGER,
+                focal_length    REAL,
+                flash           INTEGER,
+                latitude        REAL,
+                longitude       REAL,
+                altitude        REAL,
+                FOREIGN KEY(path) REFERENCES image(path)
+            );
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if !exif_exists {
+            // Media from databases created before EXIF support
+            sqlx::query("INSERT OR IGNORE INTO reindex (path) SELECT path FROM image")
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // Full-text index over media names and directories.  The rowid of each entry is the id of
+        // the corresponding image.
+        let search_exists = table_exists(&mut tx, "search").await?;
+        sqlx::query(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS search USING fts5 (
+                name,
+                dir,
+                tokenize = "unicode61 remove_diacritics 2"
+            );
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        if !search_exists {
+            // Index media from databases created before search support
+            sqlx::query("INSERT INTO search (rowid, name, dir) SELECT id, name, dir FROM image")
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // Spatial index of geotagged media.  The id of each entry is the id of the corresponding
+        // image.
+        let geo_exists = table_exists(&mut tx, "geo").await?;
+        sqlx::query(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS geo USING rtree (id, min_lat, max_lat, min_lon, max_lon);",
+        )
+        .execute(&mut *tx)
+        .await?;
+        if !geo_exists {
+            // Index media from databases created before geo support
+            sqlx::query(
+                "INSERT INTO geo (id, min_lat, max_lat, min_lon, max_lon)
+                SELECT image.id, latitude, latitude, longitude, longitude
+                FROM exif
+                    JOIN image ON image.path = exif.path
+                WHERE latitude IS NOT NULL AND longitude IS NOT NULL",
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Media whose thumbnail could not be made, retried on every scan
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS thumb_error (
+                path            TEXT PRIMARY KEY,
+                error           TEXT NOT NULL,
+                time            INTEGER NOT NULL,
+                FOREIGN KEY(path) REFERENCES image(path)
+            );
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // History of scans.  Times are unix timestamps, and the state is one of "running",
+        // "done", "stopped", "error" or "interrupted".
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS scan (
+                id              INTEGER PRIMARY KEY,
+                start_time      INTEGER NOT NULL,
+                end_time        INTEGER,
+                added           INTEGER NOT NULL DEFAULT 0,
+                updated         INTEGER NOT NULL DEFAULT 0,
+                deleted         INTEGER NOT NULL DEFAULT 0,
+                thumb_failures  INTEGER NOT NULL DEFAULT 0,
+                state           TEXT NOT NULL,
+                error           TEXT
+            );
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        // Scans still running when the server was stopped
+        sqlx::query("UPDATE scan SET state = 'interrupted' WHERE state = 'running'")
             .execute(&mut *tx)
             .await?;
 
@@ -126,9 +321,11 @@ impl<'a> Storage {
         page: usize,
         sort: &queries::Sort,
         seed: usize,
+        filter: &MediaFilter,
     ) -> Result<Vec<views::MediaDataDir>, sqlx::Error> {
         let sort_random = format!("hash({} || path)", seed);
-        sqlx::query_as(&format!("SELECT dir, name, COUNT() OVER() AS total FROM image WHERE dir LIKE ? ORDER BY {order_by} COLLATE NOCASE ASC LIMIT {limit} OFFSET {offset}",
+        let sql = format!("SELECT dir, name, COUNT() OVER() AS total FROM image WHERE dir LIKE ?{filter} ORDER BY {order_by} COLLATE NOCASE ASC LIMIT {limit} OFFSET {offset}",
+            filter = filter.sql(),
             order_by = match sort {
                 queries::Sort::Name => "path",
                 queries::Sort::Taken => "timestamp",
@@ -137,10 +334,9 @@ impl<'a> Storage {
             },
             limit = self.page_size,
             offset = page * self.page_size,
-        ))
-        .bind(format!("{}%", dir))
-        .fetch_all(&self.db)
-        .await
+        );
+        let query = sqlx::query_as(&sql).bind(format!("{}%", dir));
+        filter.bind(query).fetch_all(&self.db).await
     }
 
     pub async fn folder_media(
@@ -150,10 +346,12 @@ impl<'a> Storage {
         sort: &queries::Sort,
         seed: usize,
         reverse: bool,
+        filter: &MediaFilter,
     ) -> Result<Vec<views::MediaData>, sqlx::Error> {
         let sort_random = format!("hash({} || path)", seed);
-        sqlx::query_as(&format!(
-            "SELECT name, COUNT() OVER() AS total FROM image WHERE dir = ? ORDER BY {order_by} COLLATE NOCASE {order} LIMIT {limit} OFFSET {offset}",
+        let sql = format!(
+            "SELECT name, COUNT() OVER() AS total FROM image WHERE dir = ?{filter} ORDER BY {order_by} COLLATE NOCASE {order} LIMIT {limit} OFFSET {offset}",
+            filter = filter.sql(),
             order = if reverse { "DESC" } else { "ASC" },
             order_by = match sort {
                 queries::Sort::Name => "path",
@@ -162,9 +360,199 @@ impl<'a> Storage {
                 queries::Sort::Random => &sort_random,
             },
             limit = self.page_size,
-            offset = page * self.page_size, 
+            offset = page * self.page_size,
+        );
+        let query = sqlx::query_as(&sql).bind(dir);
+        filter.bind(query).fetch_all(&self.db).await
+    }
+
+    pub async fn search(
+        &self,
+        query: &str,
+        dir: &str,
+        page: usize,
+        sort: &queries::Sort,
+        seed: usize,
+        reverse: bool,
+    ) -> Result<Vec<views::MediaDataDir>, sqlx::Error> {
+        let match_expr = search_match_expr(query);
+        if match_expr.is_empty() {
+            return Ok(vec![]);
+        }
+        let sort_random = format!("hash({} || image.path)", seed);
+        sqlx::query_as(&format!(
+            "SELECT image.dir AS dir, image.name AS name, COUNT() OVER() AS total
+            FROM search
+                JOIN image ON image.id = search.rowid
+            WHERE search MATCH ? AND image.dir LIKE ?
+            ORDER BY {order_by} COLLATE NOCASE {order} LIMIT {limit} OFFSET {offset}",
+            order = if reverse { "DESC" } else { "ASC" },
+            order_by = match sort {
+                queries::Sort::Name => "image.path",
+                queries::Sort::Taken => "image.timestamp",
+                queries::Sort::Modified => "image.mtime",
+                queries::Sort::Random => &sort_random,
+            },
+            limit = self.page_size,
+            offset = page * self.page_size,
+        ))
+        .bind(match_expr)
+        .bind(format!("{}%", dir))
+        .fetch_all(&self.db)
+        .await
+    }
+
+    pub async fn media(&self, path: &str) -> Result<Option<tables::Image>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM image WHERE path = ?")
+            .bind(path)
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    pub async fn folder_exists(&self, dir: &str) -> Result<bool, sqlx::Error> {
+        let folder: Option<(String,)> = sqlx::query_as("SELECT path FROM folder WHERE path = ?")
+            .bind(dir)
+            .fetch_optional(&self.db)
+            .await?;
+        Ok(folder.is_some())
+    }
+
+    /// Paths of the media in a directory, and in its subdirectories when `recursive`
+    pub async fn folder_media_paths(
+        &self,
+        dir: &str,
+        recursive: bool,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let paths: Vec<(String,)> = if recursive {
+            let prefix = format!("{}/", dir.trim_end_matches('/'));
+            sqlx::query_as("SELECT path FROM image WHERE dir = ? OR substr(dir, 1, length(?)) = ? ORDER BY path")
+                .bind(dir)
+                .bind(&prefix)
+                .bind(&prefix)
+                .fetch_all(&self.db)
+                .await?
+        } else {
+            sqlx::query_as("SELECT path FROM image WHERE dir = ? ORDER BY path")
+                .bind(dir)
+                .fetch_all(&self.db)
+                .await?
+        };
+        Ok(paths.into_iter().map(|(path,)| path).collect())
+    }
+
+    pub async fn media_exif(&self, path: &str) -> Result<Option<tables::Exif>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM exif WHERE path = ?")
+            .bind(path)
+            .fetch_optional(&self.db)
+            .await
+    }
+
+    // Geotagged media under `dir` inside the bounding box.  When `zoom` is set, media are grouped
+    // into a grid of cells of 360/2^zoom degrees and each cell is returned as a single cluster
+    // represented by its most recently taken media.  A box with `min_lon` greater than `max_lon`
+    // crosses the antimeridian, and covers the longitudes from `min_lon` to 180 and from -180 to
+    // `max_lon`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn geo(
+        &self,
+        dir: &str,
+        min_lat: f64,
+        max_lat: f64,
+        min_lon: f64,
+        max_lon: f64,
+        zoom: Option<u8>,
+    ) -> Result<Vec<views::GeoCluster>, sqlx::Error> {
+        let (select, group_by) = match zoom {
+            Some(_) => (
+                "AVG(exif.latitude) AS latitude, AVG(exif.longitude) AS longitude,
+                COUNT() AS count, MAX(image.timestamp), image.dir AS dir, image.name AS name",
+                "GROUP BY CAST((exif.latitude + 90.0) / ?1 AS INTEGER),
+                    CAST((exif.longitude + 180.0) / ?1 AS INTEGER)",
+            ),
+            None => (
+                "exif.latitude AS latitude, exif.longitude AS longitude, 1 AS count,
+                image.dir AS dir, image.name AS name",
+                "",
+            ),
+        };
+        let lon = if min_lon <= max_lon {
+            "geo.min_lon >= ?4 AND geo.max_lon <= ?5"
+        } else {
+            "(geo.min_lon >= ?4 OR geo.max_lon <= ?5)"
+        };
+        let cell_size = 360.0 / 2f64.powi(zoom.unwrap_or(0).min(MAX_GEO_ZOOM) as i32);
+        sqlx::query_as(&format!(
+            "SELECT {select}
+            FROM geo
+                JOIN image ON image.id = geo.id
+                JOIN exif ON exif.path = image.path
+            WHERE geo.min_lat >= ?2 AND geo.max_lat <= ?3 AND {lon}
+                AND image.dir LIKE ?6
+            {group_by}",
+            select = select,
+            lon = lon,
+            group_by = group_by,
+        ))
+        .bind(cell_size)
+        .bind(min_lat)
+        .bind(max_lat)
+        .bind(min_lon)
+        .bind(max_lon)
+        .bind(format!("{}%", dir))
+        .fetch_all(&self.db)
+        .await
+    }
+
+    pub async fn timeline(
+        &self,
+        dir: &str,
+        group: &queries::TimelineGroup,
+    ) -> Result<Vec<views::DateCount>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT strftime('{format}', timestamp, 'unixepoch') AS date, COUNT() AS count
+            FROM image
+            WHERE dir LIKE ?
+            GROUP BY date
+            ORDER BY date",
+            format = match group {
+                queries::TimelineGroup::Year => "%Y",
+                queries::TimelineGroup::Month => "%Y-%m",
+                queries::TimelineGroup::Day => "%Y-%m-%d",
+            },
+        ))
+        .bind(format!("{}%", dir))
+        .fetch_all(&self.db)
+        .await
+    }
+
+    // Page of `folder_media_recursive` sorted by taken date that contains the first media taken
+    // at or after `timestamp`.
+    pub async fn timeline_page(&self, dir: &str, timestamp: i64) -> Result<usize, sqlx::Error> {
+        let (before,): (i64,) =
+            sqlx::query_as("SELECT COUNT() FROM image WHERE dir LIKE ? AND timestamp < ?")
+                .bind(format!("{}%", dir))
+                .bind(timestamp)
+                .fetch_one(&self.db)
+                .await?;
+        Ok(before as usize / self.page_size)
+    }
+
+    pub async fn thumb_errors(&self, page: usize) -> Result<Vec<tables::ThumbError>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT * FROM thumb_error ORDER BY path LIMIT {limit} OFFSET {offset}",
+            limit = self.page_size,
+            offset = page * self.page_size,
+        ))
+        .fetch_all(&self.db)
+        .await
+    }
+
+    pub async fn scan_history(&self, page: usize) -> Result<Vec<tables::Scan>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT * FROM scan ORDER BY id DESC LIMIT {limit} OFFSET {offset}",
+            limit = self.page_size,
+            offset = page * self.page_size,
         ))
-        .bind(dir)
         .fetch_all(&self.db)
         .await
     }
@@ -195,14 +583,219 @@ impl<'a> Storage {
         .await
     }
 
-    pub fn thumb(&self, path: &str) -> Result<Vec<u8>, ThumbError> {
+    /// Configured thumbnail size that best serves a requested size: the smallest one that is at
+    /// least as big, or the biggest one.  Without a request, `THUMB_SIZE` is requested.
+    pub fn thumb_size(&self, size: Option<u16>) -> u16 {
+        let size = size.unwrap_or(scanner::THUMB_SIZE);
+        self.thumb_sizes
+            .iter()
+            .find(|s| **s >= size)
+            .or(self.thumb_sizes.last())
+            .copied()
+            .unwrap_or(scanner::THUMB_SIZE)
+    }
+
+    pub fn thumb(&self, path: &str, size: u16) -> Result<Vec<u8>, ThumbError> {
+        let rtxn = self.thumb_db_env.read_txn()?;
+        let thumb = self.thumb_get(&rtxn, path, size)?;
+        Ok(thumb.ok_or(ThumbError::NotFound)?.to_vec())
+    }
+
+    /// Thumbnails of several media read in a single transaction, `None` for the missing ones
+    pub fn thumbs(&self, paths: &[String], size: u16) -> Result<Vec<Option<Vec<u8>>>, ThumbError> {
         let rtxn = self.thumb_db_env.read_txn()?;
-        Ok(self
-            .thumb_db
-            .get(&rtxn, path)?
-            .ok_or(ThumbError::NotFound)?
-            .to_vec())
+        let mut thumbs = Vec::with_capacity(paths.len());
+        for path in paths {
+            thumbs.push(
+                self.thumb_get(&rtxn, path, size)?
+                    .map(|thumb| thumb.to_vec()),
+            );
+        }
+        Ok(thumbs)
+    }
+
+    // Thumbnail of a media in a size, or in the closest size stored when the configured sizes
+    // changed since it was made: the smallest bigger one, or else the biggest smaller one
+    fn thumb_get<'t>(
+        &self,
+        rtxn: &'t heed::RoTxn,
+        path: &str,
+        size: u16,
+    ) -> Result<Option<&'t [u8]>, heed::Error> {
+        if let Some(thumb) = self.thumb_db.get(rtxn, &thumb_key(path, size))? {
+            return Ok(Some(thumb));
+        }
+        let mut stored = Vec::new();
+        for item in self.thumb_db.prefix_iter(rtxn, &format!("{}@", path))? {
+            let (key, thumb) = item?;
+            if let Some(key_size) = thumb_key_size(key, path) {
+                stored.push((key_size, thumb));
+            }
+        }
+        // Thumbnails made before sizes were configurable are stored under the plain path
+        if let Some(thumb) = self.thumb_db.get(rtxn, path)? {
+            stored.push((scanner::THUMB_SIZE, thumb));
+        }
+        Ok(stored
+            .into_iter()
+            .min_by_key(|(stored_size, _)| {
+                if *stored_size >= size {
+                    (false, stored_size - size)
+                } else {
+                    (true, size - stored_size)
+                }
+            })
+            .map(|(_, thumb)| thumb))
+    }
+
+    /// Store the thumbnails of a media, replacing all the previous ones
+    pub fn thumb_put(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        path: &str,
+        thumbs: &[(u16, Vec<u8>)],
+    ) -> Result<(), heed::Error> {
+        self.thumb_delete(wtxn, path)?;
+        for (size, thumb) in thumbs {
+            self.thumb_db.put(wtxn, &thumb_key(path, *size), thumb)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the thumbnails of a media in every size
+    pub fn thumb_delete(&self, wtxn: &mut heed::RwTxn, path: &str) -> Result<(), heed::Error> {
+        self.thumb_db.delete(wtxn, path)?;
+        // The prefix also matches the keys of other media whose name starts with `{path}@`
+        let mut iter = self.thumb_db.prefix_iter_mut(wtxn, &format!("{}@", path))?;
+        while let Some(item) = iter.next() {
+            let (key, _) = item?;
+            if thumb_key_size(key, path).is_some() {
+                iter.del_current()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Key of the thumbnail of a media in a size
+fn thumb_key(path: &str, size: u16) -> String {
+    format!("{}@{}", path, size)
+}
+
+// Size of the thumbnail of a media that a key belongs to, if it's one
+fn thumb_key_size(key: &str, path: &str) -> Option<u16> {
+    key.strip_prefix(path)?.strip_prefix('@')?.parse().ok()
+}
+
+// Schema of the image table.  `id` is an alias of the rowid, which keeps it stable when the
+// database is vacuumed, as it's referenced by the search and geo indexes.
+fn image_table_sql(table: &str) -> String {
+    format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS {} (
+            id              INTEGER PRIMARY KEY,
+            path            TEXT NOT NULL UNIQUE,
+            name            TEXT NOT NULL,
+            dir             TEXT NOT NULL,
+            mtime           INTEGER NOT NULL,
+            timestamp       INTEGER NOT NULL,
+            size            INTEGER,
+            width           INTEGER,
+            height          INTEGER,
+            duration_ms     INTEGER,
+            media_type      TEXT,
+            FOREIGN KEY(dir)  REFERENCES folder(path)
+        );
+        "#,
+        table
+    )
+}
+
+// Rebuild the image table of databases created by older versions, which lack the `id` column,
+// keeping the rowids that the search and geo indexes refer to.  Foreign keys are disabled in the
+// meantime, as the tables referencing the image table would be violated while it's rebuilt.
+async fn migrate_image_id(db: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut conn = db.acquire().await?;
+    let columns: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_table_info('image')")
+        .fetch_all(&mut *conn)
+        .await?;
+    if columns.is_empty() || columns.iter().any(|(name,)| name == "id") {
+        return Ok(());
     }
+    let columns = columns
+        .into_iter()
+        .map(|(name,)| name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    sqlx::query("PRAGMA foreign_keys = OFF")
+        .execute(&mut *conn)
+        .await?;
+    let mut tx = conn.begin().await?;
+    sqlx::query(&image_table_sql("image_new"))
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(&format!(
+        "INSERT INTO image_new (id, {columns}) SELECT rowid, {columns} FROM image",
+        columns = columns
+    ))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DROP TABLE image").execute(&mut *tx).await?;
+    sqlx::query("ALTER TABLE image_new RENAME TO image")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    sqlx::query("PRAGMA foreign_keys = ON")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn table_exists(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    table: &str,
+) -> Result<bool, sqlx::Error> {
+    let exists: Option<(String,)> =
+        sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table)
+            .fetch_optional(&mut **tx)
+            .await?;
+    Ok(exists.is_some())
+}
+
+// Add a column to a table unless it already exists.
+async fn add_column(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let exists: Option<(String,)> =
+        sqlx::query_as("SELECT name FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_optional(&mut **tx)
+            .await?;
+    if exists.is_none() {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+// Build an FTS5 MATCH expression from a user search query.  Every word in the query is matched
+// as a prefix of a token in the media name or directory, and all words must match.
+fn search_match_expr(query: &str) -> String {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"*", word))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 #[derive(Clone)]
@@ -226,6 +819,11 @@ pub struct StateConfig<'a> {
     pub path_mdb: &'a PathBuf,
     pub root: &'a PathBuf,
     pub n_threads: usize,
+    pub follow_symlinks: bool,
+    pub thumb_sizes: Vec<u16>,
+    pub cache_dir: Option<&'a PathBuf>,
+    /// Bytes
+    pub cache_size: u64,
     pub page_size: usize,
     pub webp_quality: usize,
     pub webp_compression: usize,
@@ -282,7 +880,7 @@ impl<'a> State {
         let storage = Storage::new(cfg).await?;
         Ok(Self {
             storage: storage.clone(),
-            scanner: Arc::new(Scanner::new(storage, cfg.n_threads)),
+            scanner: Arc::new(Scanner::new(storage, cfg.n_threads, cfg.follow_symlinks)),
             cfg: Config {
                 webp_quality: cfg.webp_quality,
                 webp_compression: cfg.webp_compression,
@@ -291,3 +889,29 @@ impl<'a> State {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_match_expr() {
+        assert_eq!(search_match_expr("deer"), r#""deer"*"#);
+        assert_eq!(
+            search_match_expr("folderB/sub deer-in"),
+            r#""folderB"* "sub"* "deer"* "in"*"#
+        );
+        assert_eq!(search_match_expr(" \"*-/ "), "");
+    }
+
+    #[test]
+    fn test_thumb_key_size() {
+        assert_eq!(
+            thumb_key_size(&thumb_key("a/b.jpg", 256), "a/b.jpg"),
+            Some(256)
+        );
+        assert_eq!(thumb_key_size("a/b.jpg@x@256", "a/b.jpg"), None);
+        assert_eq!(thumb_key_size("a/b.jpg@256", "a/b"), None);
+        assert_eq!(thumb_key_size("a/b.jpg", "a/b.jpg"), None);
+    }
+}
@@ -0,0 +1,111 @@
+use async_std::channel;
+use async_std::future;
+use async_std::sync::Arc;
+use async_std::task;
+use log::{debug, error, info};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::scanner::{self, Reply, Request, Scanner};
+
+// Time without filesystem events after which the changed directories are refreshed.  While the
+// scanner is busy, the refresh is retried with the same period.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watch the root directory and refresh the index of the directories where media is created,
+/// modified, deleted or moved.
+pub async fn watch(scanner: Arc<Scanner>, root: PathBuf) -> notify::Result<()> {
+    let (sender, receiver) = channel::unbounded();
+    let mut watcher = notify::recommended_watcher(move |res| {
+        // The receiver is only dropped when the watch ends
+        let _ = task::block_on(sender.send(res));
+    })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+    info!("Watching {:?}", root);
+
+    let mut pending = HashSet::new();
+    loop {
+        let res = if pending.is_empty() {
+            Ok(receiver.recv().await)
+        } else {
+            future::timeout(DEBOUNCE, receiver.recv()).await
+        };
+        match res {
+            Ok(Ok(Ok(event))) => pending.extend(event_dirs(&root, &event)),
+            Ok(Ok(Err(err))) => error!("watch: {:?}", err),
+            Ok(Err(_)) => break,
+            Err(_) => {
+                let dirs: Vec<PathBuf> = pending.iter().cloned().collect();
+                debug!("watch: refreshing {:?}", dirs);
+                match scanner.request(Request::Refresh(dirs)).await {
+                    Reply::OK => pending.clear(),
+                    reply => debug!("watch: scanner replied {:?}, retrying", reply),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Directories, relative to the root, whose entries are changed by a filesystem event.
+fn event_dirs(root: &Path, event: &Event) -> Vec<PathBuf> {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {}
+        _ => return Vec::new(),
+    }
+    event
+        .paths
+        .iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(root).ok()?;
+            if relative
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+            {
+                // Hidden entries are skipped by the scanner
+                return None;
+            }
+            if path.is_file() && scanner::is_media(path).unwrap_or(None).is_none() {
+                return None;
+            }
+            Some(Path::new("/").join(relative.parent()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind};
+
+    fn dirs(kind: EventKind, path: &str) -> Vec<PathBuf> {
+        let event = Event::new(kind).add_path(PathBuf::from(path));
+        event_dirs(Path::new("/media"), &event)
+    }
+
+    #[test]
+    fn test_event_dirs() {
+        let file = EventKind::Create(CreateKind::File);
+        assert_eq!(dirs(file, "/media/a/b.jpg"), vec![PathBuf::from("/a")]);
+        assert_eq!(dirs(file, "/media/.a/b.jpg"), Vec::<PathBuf>::new());
+        assert_eq!(dirs(file, "/other/b.jpg"), Vec::<PathBuf>::new());
+        assert_eq!(
+            dirs(EventKind::Remove(RemoveKind::Folder), "/media/a"),
+            vec![PathBuf::from("/")]
+        );
+        assert_eq!(
+            dirs(EventKind::Modify(ModifyKind::Any), "/media/c.mp4"),
+            vec![PathBuf::from("/")]
+        );
+        assert_eq!(
+            dirs(EventKind::Create(CreateKind::Folder), "/media"),
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(
+            dirs(EventKind::Access(AccessKind::Any), "/media/a/b.jpg"),
+            Vec::<PathBuf>::new()
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::time::SystemTime;
+
+// Signatures of the records
+const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x07064b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+// Sizes and CRC in a data descriptor after the data, and UTF-8 names
+const FLAGS: u16 = 0x0008 | 0x0800;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+// Made by Unix, so that the external attributes hold the permissions
+const VERSION_MADE_BY: u16 = 3 << 8 | VERSION_ZIP64;
+// Regular file readable by everyone
+const EXTERNAL_ATTRIBUTES: u32 = 0o100644 << 16;
+
+struct Entry {
+    name: String,
+    zip64: bool,
+    time: u16,
+    date: u16,
+    offset: u64,
+    crc: u32,
+    size: u64,
+}
+
+/// Writer of a ZIP archive that is built on the fly without seeking, so that it can be streamed.
+/// Files are stored without compression, as media are already compressed, with their CRC and
+/// size in a data descriptor after their data.  ZIP64 records are used for files and archives
+/// over 4 GiB.
+///
+/// Every method returns the bytes to append to the archive.  Each file is written with
+/// `start_file`, `data` for each chunk of its content and `end_file`, and the archive is
+/// completed with `finish`.
+pub struct ZipWriter {
+    offset: u64,
+    entries: Vec<Entry>,
+    hasher: Option<crc32fast::Hasher>,
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            entries: Vec::new(),
+            hasher: None,
+        }
+    }
+
+    /// Local header of a file with the size it's expected to have, which decides whether it
+    /// uses ZIP64.
+    pub fn start_file(&mut self, name: &str, size: u64, modified: SystemTime) -> Vec<u8> {
+        let (time, date) = dos_date_time(modified);
+        let zip64 = size >= u32::MAX as u64;
+        let entry = Entry {
+            name: name.to_string(),
+            zip64,
+            time,
+            date,
+            offset: self.offset,
+            crc: 0,
+            size: 0,
+        };
+
+        let mut buf = Vec::with_capacity(30 + name.len() + 20);
+        put_u32(&mut buf, LOCAL_FILE_HEADER);
+        put_u16(&mut buf, if zip64 { VERSION_ZIP64 } else { VERSION });
+        put_u16(&mut buf, FLAGS);
+        put_u16(&mut buf, 0); // Stored
+        put_u16(&mut buf, time);
+        put_u16(&mut buf, date);
+        put_u32(&mut buf, 0); // CRC, in the data descriptor
+        let sizes = if zip64 { u32::MAX } else { 0 };
+        put_u32(&mut buf, sizes);
+        put_u32(&mut buf, sizes);
+        put_u16(&mut buf, name.len() as u16);
+        put_u16(&mut buf, if zip64 { 20 } else { 0 });
+        buf.extend_from_slice(name.as_bytes());
+        if zip64 {
+            put_u16(&mut buf, ZIP64_EXTRA_FIELD);
+            put_u16(&mut buf, 16);
+            put_u64(&mut buf, 0);
+            put_u64(&mut buf, 0);
+        }
+
+        self.entries.push(entry);
+        self.hasher = Some(crc32fast::Hasher::new());
+        self.offset += buf.len() as u64;
+        buf
+    }
+
+    /// Account for a chunk of the data of the current file, which goes to the archive as is
+    pub fn data(&mut self, chunk: &[u8]) {
+        if let (Some(hasher), Some(entry)) = (&mut self.hasher, self.entries.last_mut()) {
+            hasher.update(chunk);
+            entry.size += chunk.len() as u64;
+            self.offset += chunk.len() as u64;
+        }
+    }
+
+    /// Data descriptor of the current file
+    pub fn end_file(&mut self) -> Vec<u8> {
+        let (hasher, entry) = match (self.hasher.take(), self.entries.last_mut()) {
+            (Some(hasher), Some(entry)) => (hasher, entry),
+            _ => return Vec::new(),
+        };
+        entry.crc = hasher.finalize();
+
+        let mut buf = Vec::with_capacity(24);
+        put_u32(&mut buf, DATA_DESCRIPTOR);
+        put_u32(&mut buf, entry.crc);
+        if entry.zip64 {
+            put_u64(&mut buf, entry.size);
+            put_u64(&mut buf, entry.size);
+        } else {
+            put_u32(&mut buf, entry.size as u32);
+            put_u32(&mut buf, entry.size as u32);
+        }
+        self.offset += buf.len() as u64;
+        buf
+    }
+
+    /// Central directory and end records
+    pub fn finish(mut self) -> Vec<u8> {
+        // The data descriptor of the last file is already counted in the offset
+        let mut buf = self.end_file();
+        let cd_offset = self.offset;
+        let cd_start = buf.len();
+        for entry in &self.entries {
+            // Fields that don't fit go to the ZIP64 extra field, in this order
+            let mut extra = Vec::new();
+            let size = if entry.zip64 || entry.size >= u32::MAX as u64 {
+                put_u64(&mut extra, entry.size);
+                put_u64(&mut extra, entry.size);
+                u32::MAX
+            } else {
+                entry.size as u32
+            };
+            let offset = if entry.offset >= u32::MAX as u64 {
+                put_u64(&mut extra, entry.offset);
+                u32::MAX
+            } else {
+                entry.offset as u32
+            };
+            let zip64 = !extra.is_empty();
+
+            put_u32(&mut buf, CENTRAL_DIRECTORY_HEADER);
+            put_u16(&mut buf, VERSION_MADE_BY);
+            put_u16(&mut buf, if zip64 { VERSION_ZIP64 } else { VERSION });
+            put_u16(&mut buf, FLAGS);
+            put_u16(&mut buf, 0); // Stored
+            put_u16(&mut buf, entry.time);
+            put_u16(&mut buf, entry.date);
+            put_u32(&mut buf, entry.crc);
+            put_u32(&mut buf, size);
+            put_u32(&mut buf, size);
+            put_u16(&mut buf, entry.name.len() as u16);
+            put_u16(&mut buf, if zip64 { 4 + extra.len() as u16 } else { 0 });
+            put_u16(&mut buf, 0); // Comment length
+            put_u16(&mut buf, 0); // Disk number
+            put_u16(&mut buf, 0); // Internal attributes
+            put_u32(&mut buf, EXTERNAL_ATTRIBUTES);
+            put_u32(&mut buf, offset);
+            buf.extend_from_slice(entry.name.as_bytes());
+            if zip64 {
+                put_u16(&mut buf, ZIP64_EXTRA_FIELD);
+                put_u16(&mut buf, extra.len() as u16);
+                buf.extend_from_slice(&extra);
+            }
+        }
+        let cd_size = (buf.len() - cd_start) as u64;
+        let cd_end = cd_offset + cd_size;
+        let count = self.entries.len() as u64;
+
+        if count >= u16::MAX as u64 || cd_size >= u32::MAX as u64 || cd_offset >= u32::MAX as u64 {
+            put_u32(&mut buf, ZIP64_END_OF_CENTRAL_DIRECTORY);
+            put_u64(&mut buf, 44); // Size of the rest of the record
+            put_u16(&mut buf, VERSION_MADE_BY);
+            put_u16(&mut buf, VERSION_ZIP64);
+            put_u32(&mut buf, 0); // Disk number
+            put_u32(&mut buf, 0); // Disk with the central directory
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, cd_size);
+            put_u64(&mut buf, cd_offset);
+
+            put_u32(&mut buf, ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR);
+            put_u32(&mut buf, 0); // Disk with the ZIP64 end of central directory
+            put_u64(&mut buf, cd_end);
+            put_u32(&mut buf, 1); // Number of disks
+        }
+
+        put_u32(&mut buf, END_OF_CENTRAL_DIRECTORY);
+        put_u16(&mut buf, 0); // Disk number
+        put_u16(&mut buf, 0); // Disk with the central directory
+        put_u16(&mut buf, count.min(u16::MAX as u64) as u16);
+        put_u16(&mut buf, count.min(u16::MAX as u64) as u16);
+        put_u32(&mut buf, cd_size.min(u32::MAX as u64) as u32);
+        put_u32(&mut buf, cd_offset.min(u32::MAX as u64) as u32);
+        put_u16(&mut buf, 0); // Comment length
+        buf
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+// MS-DOS time and date in local time, which start in 1980
+fn dos_date_time(modified: SystemTime) -> (u16, u16) {
+    let t: DateTime<Local> = modified.into();
+    if t.year() < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let time = (t.hour() << 11 | t.minute() << 5 | (t.second() / 2)) as u16;
+    let date = ((t.year().min(2107) - 1980) as u32) << 9 | t.month() << 5 | t.day();
+    (time, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(buf: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes([buf[pos], buf[pos + 1]])
+    }
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+    }
+
+    #[test]
+    fn test_zip_writer() {
+        let files: [(&str, &[u8]); 3] = [
+            ("a.jpg", b"hello"),
+            ("dir/b.jpg", b"world!"),
+            ("empty.jpg", b""),
+        ];
+        let mut zip = ZipWriter::new();
+        let mut archive = Vec::new();
+        let mut offsets = Vec::new();
+        for (name, content) in files {
+            offsets.push(archive.len() as u32);
+            archive.extend(zip.start_file(name, content.len() as u64, SystemTime::now()));
+            // Data in several chunks
+            for chunk in content.chunks(4) {
+                zip.data(chunk);
+                archive.extend_from_slice(chunk);
+            }
+            archive.extend(zip.end_file());
+        }
+        archive.extend(zip.finish());
+
+        let eocd = archive.len() - 22;
+        assert_eq!(u32_at(&archive, eocd), END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(u16_at(&archive, eocd + 10), 3);
+        let cd_size = u32_at(&archive, eocd + 12) as usize;
+        let mut pos = u32_at(&archive, eocd + 16) as usize;
+        assert_eq!(pos + cd_size, eocd);
+        for (i, (name, content)) in files.iter().enumerate() {
+            assert_eq!(u32_at(&archive, pos), CENTRAL_DIRECTORY_HEADER);
+            assert_eq!(u32_at(&archive, pos + 16), crc32fast::hash(content));
+            assert_eq!(u32_at(&archive, pos + 24), content.len() as u32);
+            let name_len = u16_at(&archive, pos + 28) as usize;
+            assert_eq!(&archive[pos + 46..pos + 46 + name_len], name.as_bytes());
+            let offset = u32_at(&archive, pos + 42) as usize;
+            assert_eq!(offset as u32, offsets[i]);
+            assert_eq!(u32_at(&archive, offset), LOCAL_FILE_HEADER);
+            // Data descriptor after the data
+            let data = offset + 30 + name_len;
+            assert_eq!(&archive[data..data + content.len()], *content);
+            assert_eq!(u32_at(&archive, data + content.len()), DATA_DESCRIPTOR);
+            assert_eq!(
+                u32_at(&archive, data + content.len() + 4),
+                crc32fast::hash(content)
+            );
+            pos += 46 + name_len;
+        }
+    }
+}
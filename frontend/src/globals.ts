@@ -85,12 +85,23 @@ export function getExt(filename) {
   return parts[parts.length-1].toLowerCase();
 }
 
+// Type of the video source by extension.  MOV and M4V are variants of MP4 that browsers play as
+// such, and the rest have no type that browsers accept, so they are detected by the browser.
+const videoTypes = {
+  "mp4": "video/mp4",
+  "m4v": "video/mp4",
+  "mov": "video/mp4",
+  "webm": "video/webm",
+  "mkv": null,
+  "avi": null,
+};
+
 // Return true if it's image, false if it's video
 export function isImg(filename) {
-  let ext = getExt(filename);
-  if (ext === "mp4" || ext === "mov") {
-    return false;
-  } else {
-    return true;
-  }
+  return !(getExt(filename) in videoTypes);
+}
+
+// Return the type of a video source, or null if the browser has to detect it
+export function videoType(filename) {
+  return videoTypes[getExt(filename)];
 }